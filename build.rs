@@ -0,0 +1,30 @@
+use std::process::Command;
+
+fn git(args: &[&str]) -> String {
+	Command::new("git")
+		.args(args)
+		.output()
+		.ok()
+		.filter(|output| output.status.success())
+		.and_then(|output| String::from_utf8(output.stdout).ok())
+		.map(|s| s.trim().to_owned())
+		.unwrap_or_else(|| "unknown".to_owned())
+}
+
+fn main() {
+	println!(
+		"cargo:rustc-env=FX_GIT_DESCRIBE={}",
+		git(&["describe", "--tags", "--always", "--dirty"])
+	);
+	println!("cargo:rustc-env=FX_GIT_HASH={}", git(&["rev-parse", "HEAD"]));
+
+	let build_timestamp = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap()
+		.as_secs();
+	println!("cargo:rustc-env=FX_BUILD_TIMESTAMP={build_timestamp}");
+
+	println!("cargo:rerun-if-changed=.git/HEAD");
+	println!("cargo:rerun-if-changed=.git/refs");
+	println!("cargo:rerun-if-changed=.git/index");
+}
@@ -0,0 +1,55 @@
+// Extra twitter-ish domains (like a self-hosted fxtwitter) that a room wants treated as twitter links.
+
+use anyhow::Context;
+use matrix_sdk::ruma::RoomId;
+use reqwest::Url;
+
+use crate::db;
+
+pub(crate) fn list(room_id: &RoomId) -> anyhow::Result<Vec<String>> {
+	let conn = db::open()?;
+	let mut stmt = conn.prepare("SELECT domain FROM RoomAllowedDomains WHERE room_id = ?1 ORDER BY domain;")?;
+	let domains = stmt
+		.query_map((room_id.as_str(),), |r| r.get(0))?
+		.collect::<Result<Vec<String>, _>>()?;
+	Ok(domains)
+}
+
+/// `list()` but empty if the database is having problems.
+pub(crate) fn get(room_id: &RoomId) -> Vec<String> {
+	list(room_id).unwrap_or_else(|e| {
+		println!("  failed to load allowed domains for {room_id}: {e:?}");
+		vec![]
+	})
+}
+
+/// Makes sure it's just a hostname (no scheme/path/port) and lowercases it.
+fn normalize(domain: &str) -> anyhow::Result<String> {
+	let url = Url::parse(&format!("https://{domain}/")).context("not a valid domain")?;
+	let host = url.host_str().context("not a valid domain")?;
+	anyhow::ensure!(
+		host.eq_ignore_ascii_case(domain) && host.contains('.'),
+		"not a valid domain: {domain}"
+	);
+	Ok(host.to_owned())
+}
+
+pub(crate) fn add(room_id: &RoomId, domain: &str) -> anyhow::Result<String> {
+	let domain = normalize(domain)?;
+	let conn = db::open()?;
+	conn.execute(
+		"INSERT OR IGNORE INTO RoomAllowedDomains (room_id, domain) VALUES (?1, ?2);",
+		(room_id.as_str(), &domain),
+	)?;
+	Ok(domain)
+}
+
+/// Returns whether anything was removed.
+pub(crate) fn remove(room_id: &RoomId, domain: &str) -> anyhow::Result<bool> {
+	let conn = db::open()?;
+	let removed = conn.execute(
+		"DELETE FROM RoomAllowedDomains WHERE room_id = ?1 AND domain = ?2;",
+		(room_id.as_str(), domain.to_ascii_lowercase()),
+	)?;
+	Ok(removed > 0)
+}
@@ -0,0 +1,39 @@
+// Other bots in our rooms. Replies to them are skipped so two link-embedding bots don't feed each other.
+
+use matrix_sdk::ruma::UserId;
+use rusqlite::OptionalExtension;
+
+use crate::db;
+
+pub(crate) fn list() -> anyhow::Result<Vec<String>> {
+	let conn = db::open()?;
+	let mut stmt = conn.prepare("SELECT user_id FROM BotAccounts ORDER BY user_id;")?;
+	let bots = stmt.query_map((), |r| r.get(0))?.collect::<Result<Vec<String>, _>>()?;
+	Ok(bots)
+}
+
+pub(crate) fn is_bot(user_id: &UserId) -> anyhow::Result<bool> {
+	let conn = db::open()?;
+	let found = conn
+		.query_row("SELECT 1 FROM BotAccounts WHERE user_id = ?1;", (user_id.as_str(),), |_| {
+			Ok(())
+		})
+		.optional()?;
+	Ok(found.is_some())
+}
+
+pub(crate) fn add(user_id: &UserId) -> anyhow::Result<()> {
+	let conn = db::open()?;
+	conn.execute(
+		"INSERT OR IGNORE INTO BotAccounts (user_id) VALUES (?1);",
+		(user_id.as_str(),),
+	)?;
+	Ok(())
+}
+
+/// Returns whether anything was removed.
+pub(crate) fn remove(user_id: &UserId) -> anyhow::Result<bool> {
+	let conn = db::open()?;
+	let removed = conn.execute("DELETE FROM BotAccounts WHERE user_id = ?1;", (user_id.as_str(),))?;
+	Ok(removed > 0)
+}
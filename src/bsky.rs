@@ -1,4 +1,5 @@
 pub(super) const TARGETS: &[&str] = &["bsky.app", "xbsky.app"];
+const _: () = assert!(crate::url_util::all_valid_hostnames(TARGETS), "typo in TARGETS");
 
 use anyhow::Context;
 use itertools::Itertools;
@@ -350,19 +351,11 @@ pub(super) async fn get_post(original_url: Url) -> anyhow::Result<crate::Post> {
 	);
 
 	if !bsky.video_uri.is_empty() {
-		post.media.push(crate::Media {
-			is_video: true,
-			url: bsky.video_uri.clone().parse()?,
-			thumbnail_url: None,
-		});
+		post.media.push(crate::Media::new(bsky.video_uri.clone().parse()?, true));
 	} else if !bsky.images.is_empty() {
 		let mut mosaic = original_url.clone();
 		mosaic.set_host(Some("mosaic.xbsky.app")).unwrap();
-		post.media.push(crate::Media {
-			is_video: false,
-			url: mosaic,
-			thumbnail_url: None,
-		});
+		post.media.push(crate::Media::new(mosaic, false));
 	}
 
 	Ok(post)
@@ -0,0 +1,11 @@
+// Filled in by build.rs. These are "unknown" when building without git (like in the Dockerfile).
+
+/// `git describe --tags --always --dirty`
+pub(crate) const GIT_DESCRIBE: &str = env!("FX_GIT_DESCRIBE");
+/// `git rev-parse HEAD`
+pub(crate) const GIT_HASH: &str = env!("FX_GIT_HASH");
+const BUILD_TIMESTAMP: &str = env!("FX_BUILD_TIMESTAMP");
+
+pub(crate) fn build_time() -> jiff::Timestamp {
+	jiff::Timestamp::from_second(BUILD_TIMESTAMP.parse().unwrap()).unwrap()
+}
@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context;
+use itertools::Itertools;
+use matrix_sdk::room::MessagesOptions;
+use matrix_sdk::ruma::OwnedEventId;
+use matrix_sdk::ruma::OwnedMxcUri;
+use matrix_sdk::ruma::OwnedRoomId;
+use matrix_sdk::ruma::OwnedUserId;
+use matrix_sdk::ruma::RoomAliasId;
+use matrix_sdk::ruma::RoomId;
+use matrix_sdk::ruma::events::AnySyncMessageLikeEvent;
+use matrix_sdk::ruma::events::AnySyncTimelineEvent;
+use matrix_sdk::ruma::events::room::message::OriginalSyncRoomMessageEvent;
+use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::HTTP;
+use crate::allowlist;
+use crate::bots;
+use crate::build_info;
+use crate::contributors;
+use crate::db;
+use crate::room_settings::RoomSettings;
+use crate::stats;
+use crate::twitter;
+use crate::whitelist;
+
+/// `!fx ping` messages that haven't come back down the sync yet.
+static PINGS: LazyLock<Mutex<HashMap<OwnedEventId, oneshot::Sender<()>>>> = LazyLock::new(Default::default);
+
+/// Called with the bot's own messages (which are otherwise ignored).
+pub(crate) fn on_own_message(event: &OriginalSyncRoomMessageEvent) {
+	if let Some(tx) = PINGS.lock().unwrap().remove(&event.event_id) {
+		let _ = tx.send(());
+	}
+}
+
+const DEBUG_MAX_CHARS: usize = 4000;
+
+/// The raw api.fxtwitter.com response for a tweet link.
+async fn debug_tweet(url: &str, api_host: &str) -> anyhow::Result<String> {
+	let url: reqwest::Url = url.parse().context("Not a URL")?;
+	let api_host = url
+		.host_str()
+		.and_then(twitter::nitter_api)
+		.map_or(api_host, |api| api.host());
+	let url = twitter::api_url(url, api_host)?;
+	HTTP.get(url)
+		.send()
+		.await
+		.context("Failed to fetch api.fxtwitter.com results")?
+		.text()
+		.await
+		.context("Failed to read api.fxtwitter.com body")
+}
+
+/// Posts `url` like a normal link and reports how long each step took.
+async fn test_link(event: &OriginalSyncRoomMessageEvent, room: &matrix_sdk::Room, url: &str) -> anyhow::Result<String> {
+	let url: reqwest::Url = url.parse().context("Not a URL")?;
+	let target = crate::Target::get(url, &allowlist::get(room.room_id())).context("Not a link the bot handles")?;
+	let start = tokio::time::Instant::now();
+	let post = crate::get_post(room, target).await?;
+	let api = start.elapsed();
+	let (uploaded, timings) = post.send(room, Some(event), &mut None).await?;
+	Ok(format!(
+		"API fetch: {}ms, media fetch: {}ms, transcode: {}ms, upload: {}ms ({uploaded} files), total: {}ms",
+		api.as_millis(),
+		timings.fetch.as_millis(),
+		timings.transcode.as_millis(),
+		timings.upload.as_millis(),
+		start.elapsed().as_millis()
+	))
+}
+
+/// Downloads `url` and makes it the bot's avatar.
+async fn set_avatar(room: &matrix_sdk::Room, url: &str) -> anyhow::Result<OwnedMxcUri> {
+	let url: reqwest::Url = url.parse().context("Not a URL")?;
+	anyhow::ensure!(url.scheme() == "https", "expected an https:// link");
+	let response = HTTP.get(url).send().await?.error_for_status()?;
+	let content_type: mime::Mime = response
+		.headers()
+		.get(reqwest::header::CONTENT_TYPE)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| v.parse().ok())
+		.context("No content type")?;
+	anyhow::ensure!(
+		content_type.type_() == mime::IMAGE,
+		"expected an image but got {content_type}"
+	);
+	let data = response.bytes().await?;
+	Ok(room.client().account().upload_avatar(&content_type, data.to_vec()).await?)
+}
+
+const MAX_DISPLAY_NAME_CHARS: usize = 255;
+
+/// How far back `!fx purge` looks (in pages of `PURGE_PAGE_SIZE` events) before giving up.
+const PURGE_MAX_PAGES: usize = 20;
+const PURGE_PAGE_SIZE: u32 = 100;
+
+/// Redacts up to `count` of the bot's latest messages in the room. Returns how many were redacted.
+async fn purge(room: &matrix_sdk::Room, count: usize) -> anyhow::Result<usize> {
+	let me = crate::MY_USER_ID.wait();
+	let mut to_redact = vec![];
+	let mut from = None;
+	for _ in 0..PURGE_MAX_PAGES {
+		let mut options = MessagesOptions::backward();
+		options.from = from;
+		options.limit = PURGE_PAGE_SIZE.into();
+		let messages = room.messages(options).await?;
+		for event in &messages.chunk {
+			// Redactions and already-redacted messages have nothing left to remove.
+			let redactable = matches!(
+				event.raw().deserialize(),
+				Ok(AnySyncTimelineEvent::MessageLike(message))
+					if message.original_content().is_some() && !matches!(message, AnySyncMessageLikeEvent::RoomRedaction(_))
+			);
+			if redactable
+				&& event.sender().as_ref() == Some(me)
+				&& let Some(event_id) = event.event_id()
+			{
+				to_redact.push(event_id);
+			}
+		}
+		to_redact.truncate(count);
+		from = messages.end;
+		if to_redact.len() >= count || from.is_none() {
+			break;
+		}
+	}
+	for event_id in &to_redact {
+		room.redact(event_id, None, None).await?;
+	}
+	Ok(to_redact.len())
+}
+
+/// Advertised in `m.room.bot.options`.
+pub(crate) const COMMANDS: &[&str] = &[
+	"allowlist",
+	"api",
+	"avatar",
+	"bot",
+	"contributors",
+	"debug",
+	"displayname",
+	"echo",
+	"embed-author",
+	"export",
+	"forget",
+	"format",
+	"gif-as-video",
+	"import",
+	"language",
+	"location",
+	"max-media-height",
+	"max-media-width",
+	"min-media-height",
+	"min-media-width",
+	"msgtype",
+	"ping",
+	"progress",
+	"purge",
+	"reload",
+	"reply-style",
+	"resend",
+	"rooms",
+	"sensitive",
+	"show-source",
+	"stats",
+	"test",
+	"thumbnail",
+	"timezone",
+	"version",
+	"whitelist",
+];
+
+/// What `!fx export` dumps and `!fx import` restores.
+#[derive(Serialize, Deserialize)]
+struct RoomExport {
+	settings: RoomSettings,
+	allowed_domains: Vec<String>,
+}
+
+fn export_room(room_id: &RoomId) -> anyhow::Result<String> {
+	let export = RoomExport {
+		settings: RoomSettings::load(room_id)?,
+		allowed_domains: allowlist::list(room_id)?,
+	};
+	Ok(serde_json::to_string_pretty(&export)?)
+}
+
+fn import_room(room_id: &RoomId, json: &str) -> anyhow::Result<()> {
+	let export: RoomExport = serde_json::from_str(json).context("failed to parse export")?;
+	export.settings.persist(room_id)?;
+	for domain in allowlist::list(room_id)? {
+		allowlist::remove(room_id, &domain)?;
+	}
+	for domain in &export.allowed_domains {
+		allowlist::add(room_id, domain)?;
+	}
+	Ok(())
+}
+
+/// `#alias:server`, `!id:server`, or nothing for the current room.
+async fn resolve_room(room: &matrix_sdk::Room, target: &str) -> anyhow::Result<OwnedRoomId> {
+	if target.is_empty() {
+		return Ok(room.room_id().to_owned());
+	}
+	if let Ok(alias) = RoomAliasId::parse(target) {
+		let response = room
+			.client()
+			.resolve_room_alias(&alias)
+			.await
+			.context("failed to resolve alias")?;
+		return Ok(response.room_id);
+	}
+	OwnedRoomId::try_from(target).context("expected a #room:server alias or !room:server ID")
+}
+
+/// Room moderators (and bot admins) can change a room's settings.
+async fn can_configure(event: &OriginalSyncRoomMessageEvent, room: &matrix_sdk::Room) -> bool {
+	if crate::is_admin(&event.sender) {
+		return true;
+	}
+	matches!(room.get_member(&event.sender).await, Ok(Some(member)) if member.can_kick())
+}
+
+/// `!fx <setting>` shows the room's setting and `!fx <setting> <value>` changes it.
+async fn room_setting<T>(
+	event: &OriginalSyncRoomMessageEvent,
+	room: &matrix_sdk::Room,
+	value: &str,
+	name: &str,
+	field: fn(&mut RoomSettings) -> &mut T,
+) where
+	T: std::str::FromStr<Err=anyhow::Error>+std::fmt::Display,
+{
+	let mut settings = RoomSettings::get(room.room_id());
+	let reply = match value {
+		"" => format!("{name}: {}", field(&mut settings)),
+		_ if !can_configure(event, room).await => return,
+		value => match value.parse::<T>() {
+			Ok(value) => {
+				*field(&mut settings) = value;
+				match settings.persist(room.room_id()) {
+					Ok(()) => format!("{name}: {}", field(&mut settings)),
+					Err(e) => format!("failed to save settings: {e:#}"),
+				}
+			},
+			Err(e) => format!("{e}"),
+		},
+	};
+	let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+}
+
+// `!fx <cmd> <rest>`
+pub(crate) async fn handle(event: &OriginalSyncRoomMessageEvent, room: &matrix_sdk::Room, args: &str) {
+	let (cmd, rest) = args.split_once(' ').unwrap_or((args, ""));
+
+	match cmd {
+		"allowlist" => {
+			let (action, domain) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+			let domain = domain.trim();
+			let reply = match action {
+				"" | "list" => allowlist::list(room.room_id()).map(|domains| {
+					if domains.is_empty() {
+						"no extra domains".to_owned()
+					} else {
+						domains.join(", ")
+					}
+				}),
+				_ if !crate::is_admin(&event.sender) => return,
+				"add" => allowlist::add(room.room_id(), domain).map(|domain| format!("added {domain}")),
+				"remove" => allowlist::remove(room.room_id(), domain).map(|removed| {
+					if removed {
+						format!("removed {domain}")
+					} else {
+						format!("{domain} wasn't in the allowlist")
+					}
+				}),
+				_ => return,
+			};
+			let reply = reply.unwrap_or_else(|e| format!("{e:#}"));
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"api" => {
+			// Anyone can look, but pointing the bot at another server is for bot admins.
+			if !rest.trim().is_empty() && !crate::is_admin(&event.sender) {
+				return;
+			}
+			room_setting(event, room, rest.trim(), "api", |s| &mut s.api_endpoint).await;
+		},
+		"avatar" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let reply = set_avatar(room, rest.trim())
+				.await
+				.map_or_else(|e| format!("{e:#}"), |mxc| format!("avatar set to {mxc}"));
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"bot" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let (action, user_id) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+			let reply = match action {
+				"" | "list" => bots::list().map(|bots| {
+					if bots.is_empty() {
+						"no bot accounts".to_owned()
+					} else {
+						bots.join(", ")
+					}
+				}),
+				"add" | "remove" => match OwnedUserId::try_from(user_id.trim()) {
+					Err(e) => Ok(format!("bad user id: {e}")),
+					Ok(user_id) if action == "add" => bots::add(&user_id).map(|()| format!("added {user_id}")),
+					Ok(user_id) => bots::remove(&user_id).map(|removed| {
+						if removed {
+							format!("removed {user_id}")
+						} else {
+							format!("{user_id} wasn't a bot account")
+						}
+					}),
+				},
+				_ => return,
+			};
+			let reply = reply.unwrap_or_else(|e| format!("{e:#}"));
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"contributors" => {
+			let reply = match contributors::top(room.room_id(), 5) {
+				Ok(top) if top.is_empty() => "no links shared yet".to_owned(),
+				Ok(top) => top
+					.iter()
+					.enumerate()
+					.map(|(i, (user_id, count))| format!("{}. {user_id}: {count}", i + 1))
+					.join("\n"),
+				Err(e) => format!("failed to read contributors: {e:#}"),
+			};
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"debug" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let api_endpoint = RoomSettings::get(room.room_id()).api_endpoint;
+			let content = match debug_tweet(rest.trim(), api_endpoint.host()).await {
+				Ok(json) => {
+					let note = if json.chars().count() > DEBUG_MAX_CHARS {
+						format!("(truncated to {DEBUG_MAX_CHARS} characters)")
+					} else {
+						"".into()
+					};
+					let json: String = json.chars().take(DEBUG_MAX_CHARS).collect();
+					RoomMessageEventContent::text_html(
+						format!("{json}\n{note}"),
+						format!(
+							"<pre><code class=\"language-json\">{}</code></pre>{note}",
+							htmlize::escape_text(&json)
+						),
+					)
+				},
+				Err(e) => RoomMessageEventContent::text_plain(format!("{e:#}")),
+			};
+			let _ = room.send(content).await;
+		},
+		"export" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let content = match export_room(room.room_id()) {
+				Ok(json) => RoomMessageEventContent::text_html(
+					json.clone(),
+					format!(
+						"<pre><code class=\"language-json\">{}</code></pre>",
+						htmlize::escape_text(&json)
+					),
+				),
+				Err(e) => RoomMessageEventContent::text_plain(format!("failed to export: {e:#}")),
+			};
+			let _ = room.send(content).await;
+		},
+		"import" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let reply = match import_room(room.room_id(), rest.trim()) {
+				Ok(()) => "imported".to_owned(),
+				Err(e) => format!("failed to import: {e:#}"),
+			};
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"purge" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let count = match rest.trim().split_once(' ') {
+				None if rest.trim().is_empty() => Ok(10),
+				Some(("last", n)) => n.trim().parse::<usize>().context("expected !fx purge [last N]"),
+				_ => Err(anyhow::anyhow!("expected !fx purge [last N]")),
+			};
+			let reply = match count {
+				Ok(count) => {
+					let count = count.min(crate::ARGS.purge_limit);
+					purge(room, count)
+						.await
+						.map_or_else(|e| format!("{e:#}"), |n| format!("redacted {n} messages"))
+				},
+				Err(e) => format!("{e:#}"),
+			};
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"reload" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let reply = match crate::read_opengraphers().await {
+				Ok(new) => {
+					let mut og = crate::OPENGRAPHERS.write().unwrap();
+					let added = new.iter().filter(|site| !og.contains(site)).join(", ");
+					let removed = og.iter().filter(|site| !new.contains(site)).join(", ");
+					*og = new;
+					let reply = format!("reloaded opengraph sites (added: [{added}], removed: [{removed}])");
+					println!("{reply}");
+					reply
+				},
+				Err(e) => format!("kept the old opengraph sites: {e:#}"),
+			};
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"displayname" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let name = rest.trim();
+			let reply = if name.is_empty() || name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+				format!("expected a name of 1 to {MAX_DISPLAY_NAME_CHARS} characters")
+			} else {
+				match room.client().account().set_display_name(Some(name)).await {
+					Ok(()) => format!("display name set to {name}"),
+					Err(e) => format!("{e:#}"),
+				}
+			};
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"embed-author" => room_setting(event, room, rest.trim(), "embed author", |s| &mut s.embed_author).await,
+		"echo" => room_setting(event, room, rest.trim(), "echo", |s| &mut s.echo).await,
+		"forget" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let reply = match resolve_room(room, rest.trim()).await {
+				Ok(room_id) => match db::forget_room(&room_id) {
+					Ok(removed) => format!("forgot {room_id} ({removed} rows)"),
+					Err(e) => format!("failed to forget {room_id}: {e:#}"),
+				},
+				Err(e) => format!("{e:#}"),
+			};
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"format" => room_setting(event, room, rest.trim(), "format", |s| &mut s.format).await,
+		"gif-as-video" => room_setting(event, room, rest.trim(), "gif as video", |s| &mut s.gif_as_video).await,
+		"language" => room_setting(event, room, rest.trim(), "language", |s| &mut s.language).await,
+		"location" => room_setting(event, room, rest.trim(), "location", |s| &mut s.location).await,
+		"max-media-height" => {
+			room_setting(event, room, rest.trim(), "maximum media height", |s| &mut s.max_media_height).await;
+		},
+		"max-media-width" => {
+			room_setting(event, room, rest.trim(), "maximum media width", |s| &mut s.max_media_width).await;
+		},
+		"min-media-height" => {
+			room_setting(event, room, rest.trim(), "minimum media height", |s| &mut s.min_media_height).await;
+		},
+		"min-media-width" => {
+			room_setting(event, room, rest.trim(), "minimum media width", |s| &mut s.min_media_width).await;
+		},
+		"msgtype" => room_setting(event, room, rest.trim(), "msgtype", |s| &mut s.msgtype).await,
+		"progress" => room_setting(event, room, rest.trim(), "progress message", |s| &mut s.progress).await,
+		"reply-style" => room_setting(event, room, rest.trim(), "reply style", |s| &mut s.reply_style).await,
+		"resend" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			// Edits never get here so one `!fx resend` only ever posts once.
+			let target = rest
+				.trim()
+				.parse()
+				.ok()
+				.and_then(|url| crate::Target::get(url, &allowlist::get(room.room_id())));
+			match target {
+				Some(target) => crate::process_target(room, target, Some(event)).await,
+				None => {
+					let _ = room
+						.send(RoomMessageEventContent::text_plain("not a link the bot handles"))
+						.await;
+				},
+			}
+		},
+		"rooms" => {
+			if !crate::is_admin(&event.sender) || !matches!(rest.trim(), "" | "count") {
+				return;
+			}
+			let joined = room.client().joined_rooms().len();
+			let max = crate::ARGS
+				.max_rooms
+				.map_or_else(|| "unlimited".to_owned(), |max| max.to_string());
+			let content = RoomMessageEventContent::text_plain(format!("rooms: {joined}/{max}"));
+			let _ = room.send(content).await;
+		},
+		"sensitive" => room_setting(event, room, rest.trim(), "sensitive media", |s| &mut s.sensitive).await,
+		"ping" => {
+			// Event handlers run inside the sync loop, so waiting here would hold up the very sync that brings the pong back.
+			let room = room.clone();
+			tokio::spawn(async move {
+				let start = tokio::time::Instant::now();
+				let Ok(sent) = room.send(RoomMessageEventContent::text_plain("pong")).await else {
+					return;
+				};
+				let event_id = sent.response.event_id;
+				let (tx, rx) = oneshot::channel();
+				PINGS.lock().unwrap().insert(event_id.clone(), tx);
+				let content = if let Ok(Ok(())) = tokio::time::timeout(Duration::from_secs(30), rx).await {
+					format!("round trip: {}ms", start.elapsed().as_millis())
+				} else {
+					PINGS.lock().unwrap().remove(&event_id);
+					"didn't see the pong come back from the homeserver after 30s".to_owned()
+				};
+				let _ = room.send(RoomMessageEventContent::text_plain(content)).await;
+			});
+		},
+		"show-source" => room_setting(event, room, rest.trim(), "show source", |s| &mut s.show_source).await,
+		"stats" => {
+			let reply = match rest.trim() {
+				"" => {
+					let language = RoomSettings::get(room.room_id()).language;
+					stats::get(room.room_id()).map(|stats| stats.localized(language))
+				},
+				_ if !crate::is_admin(&event.sender) => return,
+				"global" => stats::global().map(|(stats, rooms)| format!("{stats} | rooms: {rooms}")),
+				"reset" => stats::reset(room.room_id()).map(|()| "stats reset".to_owned()),
+				_ => return,
+			};
+			let reply = reply.unwrap_or_else(|e| format!("failed to read stats: {e:#}"));
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"test" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let reply = test_link(event, room, rest.trim()).await.unwrap_or_else(|e| format!("{e:#}"));
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		"thumbnail" => room_setting(event, room, rest.trim(), "thumbnails", |s| &mut s.thumbnails).await,
+		"timezone" => room_setting(event, room, rest.trim(), "timezone", |s| &mut s.timezone).await,
+		"version" => {
+			let content = RoomMessageEventContent::text_plain(format!(
+				"{} {} ({}, built {})\ncommit {}",
+				env!("CARGO_PKG_NAME"),
+				env!("CARGO_PKG_VERSION"),
+				build_info::GIT_DESCRIBE,
+				build_info::build_time().strftime("%F %T"),
+				build_info::GIT_HASH
+			));
+			let _ = room.send(content).await;
+		},
+		"whitelist" => {
+			if !crate::is_admin(&event.sender) {
+				return;
+			}
+			let (action, target) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+			let reply = match action {
+				"" | "list" => whitelist::list().map(|rooms| {
+					if rooms.is_empty() {
+						"no whitelisted rooms".to_owned()
+					} else {
+						rooms.join(", ")
+					}
+				}),
+				"add" | "remove" => match resolve_room(room, target.trim()).await {
+					Err(e) => Ok(format!("{e:#}")),
+					Ok(room_id) if action == "add" => whitelist::add(&room_id).map(|()| format!("added {room_id}")),
+					Ok(room_id) => whitelist::remove(&room_id).map(|removed| {
+						if removed {
+							format!("removed {room_id}")
+						} else {
+							format!("{room_id} wasn't whitelisted")
+						}
+					}),
+				},
+				_ => return,
+			};
+			let reply = reply.unwrap_or_else(|e| format!("{e:#}"));
+			let _ = room.send(RoomMessageEventContent::text_plain(reply)).await;
+		},
+		_ => (),
+	}
+}
@@ -0,0 +1,38 @@
+// Who shares the most links in each room, for `!fx contributors`.
+
+use matrix_sdk::ruma::RoomId;
+use matrix_sdk::ruma::UserId;
+
+use crate::db;
+
+fn try_record(room_id: &RoomId, user_id: &UserId, links: i64) -> anyhow::Result<()> {
+	let conn = db::open()?;
+	conn.execute(
+		"
+		INSERT INTO Contributors (room_id, user_id, count)
+		VALUES (?1, ?2, ?3)
+		ON CONFLICT (room_id, user_id)
+		DO UPDATE SET count = count + ?3;
+		",
+		(room_id.as_str(), user_id.as_str(), links),
+	)?;
+	Ok(())
+}
+
+/// Counts `links` more links for `user_id`. Like the stats, this isn't worth failing a post over.
+pub(crate) fn record(room_id: &RoomId, user_id: &UserId, links: i64) {
+	if let Err(e) = try_record(room_id, user_id, links) {
+		println!("  failed to record contributor {user_id} in {room_id}: {e:?}");
+	}
+}
+
+/// The `limit` users with the most links in the room, most first.
+pub(crate) fn top(room_id: &RoomId, limit: usize) -> anyhow::Result<Vec<(String, i64)>> {
+	let conn = db::open()?;
+	let mut stmt =
+		conn.prepare("SELECT user_id, count FROM Contributors WHERE room_id = ?1 ORDER BY count DESC, user_id LIMIT ?2;")?;
+	let top = stmt
+		.query_map((room_id.as_str(), limit as i64), |r| Ok((r.get(0)?, r.get(1)?)))?
+		.collect::<Result<Vec<_>, _>>()?;
+	Ok(top)
+}
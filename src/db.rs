@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+
+use matrix_sdk::ruma::RoomId;
+
+use crate::ARGS;
+
+/// The bot's own tables. These live next to `FxSessionData` in fxsession.sqlite3.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS RoomSettings (room_id TEXT PRIMARY KEY, settings TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS TweetStats (
+	room_id TEXT PRIMARY KEY,
+	tweets_processed INTEGER NOT NULL DEFAULT 0,
+	media_uploaded INTEGER NOT NULL DEFAULT 0,
+	errors INTEGER NOT NULL DEFAULT 0,
+	last_activity INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS RoomAllowedDomains (room_id TEXT NOT NULL, domain TEXT NOT NULL, PRIMARY KEY (room_id, domain));
+CREATE TABLE IF NOT EXISTS BotAccounts (user_id TEXT PRIMARY KEY);
+CREATE TABLE IF NOT EXISTS WhitelistedRooms (room_id TEXT PRIMARY KEY);
+CREATE TABLE IF NOT EXISTS Contributors (
+	room_id TEXT NOT NULL,
+	user_id TEXT NOT NULL,
+	count INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY (room_id, user_id)
+);
+";
+
+pub(crate) fn open() -> anyhow::Result<rusqlite::Connection> {
+	let conn = rusqlite::Connection::open(ARGS.database_dir.join("fxsession.sqlite3"))?;
+	conn.execute_batch(SCHEMA)?;
+	Ok(conn)
+}
+
+/// Per-room tables that are only useful while the bot is in the room.
+/// `WhitelistedRooms` isn't here since rooms get whitelisted before the bot joins them.
+const ROOM_TABLES: &[&str] = &["RoomSettings", "TweetStats", "RoomAllowedDomains", "Contributors"];
+
+/// Deletes everything stored for a room (including its whitelist entry). Returns how many rows went.
+pub(crate) fn forget_room(room_id: &RoomId) -> anyhow::Result<usize> {
+	let conn = open()?;
+	let mut removed = 0;
+	for table in ROOM_TABLES.iter().chain(&["WhitelistedRooms"]) {
+		removed += conn.execute(&format!("DELETE FROM {table} WHERE room_id = ?1;"), (room_id.as_str(),))?;
+	}
+	Ok(removed)
+}
+
+/// Cleans up after rooms the bot left (or that were deleted) while it wasn't looking.
+pub(crate) fn forget_rooms_except(joined: &HashSet<String>) -> anyhow::Result<usize> {
+	let conn = open()?;
+	let mut removed = 0;
+	for table in ROOM_TABLES {
+		let mut stmt = conn.prepare(&format!("SELECT DISTINCT room_id FROM {table};"))?;
+		let rooms = stmt.query_map((), |r| r.get(0))?.collect::<Result<Vec<String>, _>>()?;
+		for room_id in rooms.iter().filter(|room_id| !joined.contains(*room_id)) {
+			removed += conn.execute(&format!("DELETE FROM {table} WHERE room_id = ?1;"), (room_id,))?;
+		}
+	}
+	Ok(removed)
+}
@@ -0,0 +1,62 @@
+// `GET /healthz` for liveness probes. Hand-rolled since it only ever answers the one thing.
+
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use anyhow::Context;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+
+/// Binds up front so a bad `--bind-metrics` fails at startup instead of in the background.
+pub(crate) async fn bind(addr: SocketAddr) -> anyhow::Result<TcpListener> {
+	TcpListener::bind(addr)
+		.await
+		.with_context(|| format!("failed to bind {addr}"))
+}
+
+/// Runs separately from the sync loop so it keeps answering while sessions restart.
+pub(crate) async fn serve(listener: TcpListener) {
+	let started = Instant::now();
+	loop {
+		match listener.accept().await {
+			Ok((stream, _)) => {
+				tokio::spawn(async move {
+					if let Err(e) = respond(stream, started).await {
+						println!("health check: {e:?}");
+					}
+				});
+			},
+			Err(e) => println!("health check accept: {e:?}"),
+		}
+	}
+}
+
+async fn respond(mut stream: TcpStream, started: Instant) -> anyhow::Result<()> {
+	let mut buf = Vec::with_capacity(1024);
+	stream.read_buf(&mut buf).await?;
+	let request = String::from_utf8_lossy(&buf);
+	let path = request
+		.lines()
+		.next()
+		.and_then(|line| line.strip_prefix("GET "))
+		.and_then(|rest| rest.split(' ').next())
+		.unwrap_or_default();
+	let (status, body) = if path == "/healthz" || path.starts_with("/healthz?") {
+		let uptime_secs = started.elapsed().as_secs();
+		(
+			"200 OK",
+			serde_json::json!({ "status": "ok", "uptime_secs": uptime_secs }).to_string(),
+		)
+	} else {
+		("404 Not Found", serde_json::json!({ "status": "not found" }).to_string())
+	};
+	let response = format!(
+		"HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		body.len()
+	);
+	stream.write_all(response.as_bytes()).await?;
+	stream.shutdown().await?;
+	Ok(())
+}
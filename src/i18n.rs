@@ -0,0 +1,119 @@
+// Translations for the things the bot says on its own (errors, stats, etc). Post text is left alone.
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Language {
+	#[default]
+	En,
+	De,
+	Fr,
+	Ja,
+}
+
+impl std::str::FromStr for Language {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		match s {
+			"en" => Ok(Language::En),
+			"de" => Ok(Language::De),
+			"fr" => Ok(Language::Fr),
+			"ja" => Ok(Language::Ja),
+			_ => anyhow::bail!("expected one of: en, de, fr, ja"),
+		}
+	}
+}
+
+impl std::fmt::Display for Language {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Language::En => "en",
+			Language::De => "de",
+			Language::Fr => "fr",
+			Language::Ja => "ja",
+		})
+	}
+}
+
+#[rustfmt::skip]
+static STRINGS: LazyLock<HashMap<Language, HashMap<&'static str, &'static str>>> = LazyLock::new(|| {
+	HashMap::from([
+		(Language::En, HashMap::from([
+			("tweet_private", "That tweet is from a private account"),
+			("tweet_not_found", "That tweet doesn't exist or was deleted"),
+			("api_error", "api.fxtwitter.com is having problems, try again later"),
+			("post_timeout", "Timed out fetching media for this post"),
+			("fetching", "Fetching post…"),
+			("stats_posts", "posts"),
+			("stats_media", "media"),
+			("stats_errors", "errors"),
+			("stats_last_activity", "last activity"),
+			("error_prefix", "Failed to process link"),
+			("error_network", "couldn't reach the site"),
+			("error_parse", "the site sent something unexpected"),
+			("error_matrix", "couldn't post it to this room"),
+			("error_other", "something went wrong"),
+		])),
+		(Language::De, HashMap::from([
+			("tweet_private", "Dieser Tweet stammt von einem privaten Konto"),
+			("tweet_not_found", "Dieser Tweet existiert nicht oder wurde gelöscht"),
+			("api_error", "api.fxtwitter.com hat gerade Probleme, versuch es später nochmal"),
+			("post_timeout", "Zeitüberschreitung beim Laden der Medien für diesen Beitrag"),
+			("fetching", "Beitrag wird geladen…"),
+			("stats_posts", "Beiträge"),
+			("stats_media", "Medien"),
+			("stats_errors", "Fehler"),
+			("stats_last_activity", "letzte Aktivität"),
+			("error_prefix", "Link konnte nicht verarbeitet werden"),
+			("error_network", "die Seite ist nicht erreichbar"),
+			("error_parse", "die Seite hat etwas Unerwartetes geschickt"),
+			("error_matrix", "konnte nicht in diesem Raum gepostet werden"),
+			("error_other", "etwas ist schiefgelaufen"),
+		])),
+		(Language::Fr, HashMap::from([
+			("tweet_private", "Ce tweet provient d'un compte privé"),
+			("tweet_not_found", "Ce tweet n'existe pas ou a été supprimé"),
+			("api_error", "api.fxtwitter.com rencontre des problèmes, réessayez plus tard"),
+			("post_timeout", "Délai dépassé lors de la récupération des médias de ce post"),
+			("fetching", "Récupération du post…"),
+			("stats_posts", "posts"),
+			("stats_media", "médias"),
+			("stats_errors", "erreurs"),
+			("stats_last_activity", "dernière activité"),
+			("error_prefix", "Impossible de traiter le lien"),
+			("error_network", "le site est injoignable"),
+			("error_parse", "le site a renvoyé quelque chose d'inattendu"),
+			("error_matrix", "impossible de publier dans ce salon"),
+			("error_other", "une erreur est survenue"),
+		])),
+		(Language::Ja, HashMap::from([
+			("tweet_private", "このツイートは非公開アカウントのものです"),
+			("tweet_not_found", "このツイートは存在しないか削除されました"),
+			("api_error", "api.fxtwitter.com に問題が発生しています。しばらくしてから再試行してください"),
+			("post_timeout", "この投稿のメディア取得がタイムアウトしました"),
+			("fetching", "投稿を取得中…"),
+			("stats_posts", "投稿"),
+			("stats_media", "メディア"),
+			("stats_errors", "エラー"),
+			("stats_last_activity", "最終アクティビティ"),
+			("error_prefix", "リンクを処理できませんでした"),
+			("error_network", "サイトに接続できませんでした"),
+			("error_parse", "サイトから予期しない応答がありました"),
+			("error_matrix", "このルームに投稿できませんでした"),
+			("error_other", "エラーが発生しました"),
+		])),
+	])
+});
+
+/// Looks up a message ID, falling back to English and then to `id` itself (so non-ID text like API error messages passes through).
+pub(crate) fn tr(lang: Language, id: &str) -> &str {
+	[lang, Language::En]
+		.iter()
+		.find_map(|lang| STRINGS.get(lang)?.get(id).copied())
+		.unwrap_or(id)
+}
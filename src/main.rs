@@ -2,15 +2,52 @@
 // Copyright 2026 rtldg <rtldg@protonmail.com>
 // Copyright ????-???? matrix-rust-sdk contributors
 
+// These shadow the std ones for the whole crate so that everything the bot prints also ends up in `--log-file`.
+// The terminal layer skips the `stdout`/`stderr` targets since the line was already printed.
+macro_rules! println {
+	($($arg:tt)*) => {{
+		let line = format!($($arg)*);
+		std::println!("{line}");
+		tracing::info!(target: "stdout", "{line}");
+	}};
+}
+macro_rules! eprintln {
+	($($arg:tt)*) => {{
+		let line = format!($($arg)*);
+		std::eprintln!("{line}");
+		tracing::warn!(target: "stderr", "{line}");
+	}};
+}
+
+mod allowlist;
+mod bots;
 mod bsky;
+mod build_info;
+mod commands;
+mod contributors;
+mod db;
+mod health;
+mod i18n;
 mod misskey;
 mod opengraph;
 mod pixiv;
+mod resize;
+mod room_settings;
+mod send_queue;
+mod stats;
+mod transcode;
 mod twitter;
+mod url_util;
 mod verification;
+mod webhook;
+mod whitelist;
 
+use std::collections::HashSet;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::LazyLock;
 use std::sync::OnceLock;
 use std::sync::RwLock;
@@ -18,6 +55,7 @@ use std::time::Duration;
 
 use anyhow::Context;
 use clap::Parser;
+use itertools::Itertools;
 use matrix_sdk::RoomState;
 use matrix_sdk::attachment::AttachmentConfig;
 use matrix_sdk::attachment::BaseImageInfo;
@@ -25,17 +63,52 @@ use matrix_sdk::attachment::BaseVideoInfo;
 use matrix_sdk::attachment::Thumbnail;
 use matrix_sdk::authentication::matrix::MatrixSession;
 use matrix_sdk::config::SyncSettings;
+use matrix_sdk::room::IncludeRelations;
+use matrix_sdk::room::RelationsOptions;
+use matrix_sdk::room::reply::EnforceThread;
+use matrix_sdk::room::reply::Reply;
+use matrix_sdk::ruma::EventId;
+use matrix_sdk::ruma::OwnedEventId;
+use matrix_sdk::ruma::OwnedRoomOrAliasId;
 use matrix_sdk::ruma::OwnedUserId;
+use matrix_sdk::ruma::RoomAliasId;
+use matrix_sdk::ruma::UserId;
 use matrix_sdk::ruma::api::client::filter::FilterDefinition;
+use matrix_sdk::ruma::api::client::membership::leave_room;
+use matrix_sdk::ruma::api::error::ErrorKind;
+use matrix_sdk::ruma::api::error::UnknownTokenErrorData;
+use matrix_sdk::ruma::events::AnySyncMessageLikeEvent;
+use matrix_sdk::ruma::events::AnySyncTimelineEvent;
+use matrix_sdk::ruma::events::EmptyStateKey;
+use matrix_sdk::ruma::events::SyncMessageLikeEvent;
+use matrix_sdk::ruma::events::macros::EventContent;
 use matrix_sdk::ruma::events::relation::RelationType;
+use matrix_sdk::ruma::events::room::member::MembershipState;
 use matrix_sdk::ruma::events::room::member::StrippedRoomMemberEvent;
+use matrix_sdk::ruma::events::room::message::AddMentions;
+use matrix_sdk::ruma::events::room::message::ForwardThread;
+use matrix_sdk::ruma::events::room::message::LocationMessageEventContent;
 use matrix_sdk::ruma::events::room::message::MessageType;
 use matrix_sdk::ruma::events::room::message::OriginalSyncRoomMessageEvent;
+use matrix_sdk::ruma::events::room::message::Relation;
+use matrix_sdk::ruma::events::room::message::ReplacementMetadata;
+use matrix_sdk::ruma::events::room::message::ReplyWithinThread;
 use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
-use rand::Rng;
+use matrix_sdk::ruma::events::room::message::TextMessageEventContent;
 use reqwest::Url;
+use room_settings::Format;
+use room_settings::Msgtype;
+use room_settings::ReplyStyle;
+use room_settings::SensitiveMode;
+use room_settings::Toggle;
 use serde::Deserialize;
 use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::AbortOnDropHandle;
+use tracing_subscriber::Layer as _;
+use tracing_subscriber::layer::SubscriberExt as _;
+use tracing_subscriber::util::SubscriberInitExt as _;
 
 #[derive(PartialEq, Debug)]
 enum Target {
@@ -47,10 +120,15 @@ enum Target {
 }
 
 impl Target {
-	fn get(url: Url) -> Option<Target> {
+	/// `room_domains` are extra twitter domains from `!fx allowlist`.
+	fn get(url: Url, room_domains: &[String]) -> Option<Target> {
 		let host = url.host_str()?.to_ascii_lowercase();
-		if twitter::TARGETS.contains(&host.as_str()) && url.path().contains("/status/") {
-			Some(Target::Twitter(url))
+		if (twitter::TARGETS.contains(&host.as_str())
+			|| room_domains.contains(&host)
+			|| twitter::nitter_api(&host).is_some())
+			&& url_util::is_valid_tweet_url(&url)
+		{
+			twitter::passes_filter(&url).then_some(Target::Twitter(url))
 		} else if bsky::TARGETS.contains(&host.as_str()) && url.path().contains("/post/") {
 			Some(Target::Bsky(url))
 		} else if pixiv::TARGETS.contains(&host.as_str()) {
@@ -65,6 +143,111 @@ impl Target {
 			}
 		}
 	}
+
+	/// Used to skip duplicate posts in a message, like x.com & twitter.com links to the same tweet.
+	fn dedup_key(&self) -> String {
+		match self {
+			Target::Twitter(url) => match twitter::tweet_id(url) {
+				Some(id) => format!("twitter:{id}"),
+				None => url.to_string(),
+			},
+			Target::Bsky(url) | Target::Misskey(url) | Target::Opengraph(url) | Target::Pixiv(url) => url.to_string(),
+		}
+	}
+}
+
+/// An error that should be shown to the room instead of just being printed to the console.
+/// Can be an `i18n` message ID.
+#[derive(Debug)]
+struct PublicError(String);
+
+impl std::fmt::Display for PublicError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl std::error::Error for PublicError {}
+
+/// Rough `i18n` message ID for what went wrong, since the room doesn't need the whole error chain.
+fn error_reason(e: &anyhow::Error) -> &str {
+	if let Some(e) = e.downcast_ref::<PublicError>() {
+		return &e.0;
+	}
+	for cause in e.chain() {
+		if cause.is::<serde_json::Error>() {
+			return "error_parse";
+		}
+		if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+			return if e.is_decode() { "error_parse" } else { "error_network" };
+		}
+		if cause.is::<reqwest_middleware::Error>() {
+			return "error_network";
+		}
+		if cause.is::<matrix_sdk::Error>() || cause.is::<matrix_sdk::HttpError>() {
+			return "error_matrix";
+		}
+	}
+	"error_other"
+}
+
+/// Relates `content` to `trigger` the way the room's `reply-style` wants.
+fn reply_to_trigger(
+	content: RoomMessageEventContent,
+	trigger: &OriginalSyncRoomMessageEvent,
+	reply_style: ReplyStyle,
+) -> RoomMessageEventContent {
+	match reply_style {
+		ReplyStyle::None => content.make_reply_to(trigger, ForwardThread::No, AddMentions::No),
+		ReplyStyle::Thread => content.make_for_thread(trigger, ReplyWithinThread::No, AddMentions::No),
+		ReplyStyle::Mention => content.make_reply_to(trigger, ForwardThread::No, AddMentions::Yes),
+	}
+}
+
+/// Sends `content` as a reply to `trigger`, or as an edit of the `!fx progress` message if there is one.
+async fn send_status(
+	room: &matrix_sdk::Room,
+	trigger: Option<&OriginalSyncRoomMessageEvent>,
+	placeholder: Option<OwnedEventId>,
+	mut content: RoomMessageEventContent,
+) {
+	if let Some(placeholder) = placeholder {
+		content = content.make_replacement(ReplacementMetadata::new(placeholder, None));
+	} else if let Some(trigger) = trigger {
+		let reply_style = room_settings::RoomSettings::get(room.room_id()).reply_style;
+		content = reply_to_trigger(content, trigger, reply_style);
+	}
+	let _ = room.send(content).await;
+}
+
+/// Lets the room know the link was looked at but something went wrong.
+async fn send_error_reply(
+	room: &matrix_sdk::Room,
+	trigger: Option<&OriginalSyncRoomMessageEvent>,
+	placeholder: Option<OwnedEventId>,
+	e: &anyhow::Error,
+) {
+	let language = room_settings::RoomSettings::get(room.room_id()).language;
+	let content = RoomMessageEventContent::notice_plain(format!(
+		"⚠️ {}: {}",
+		i18n::tr(language, "error_prefix"),
+		i18n::tr(language, error_reason(e))
+	));
+	send_status(room, trigger, placeholder, content).await;
+}
+
+/// The `!fx progress` message that the post gets edited into.
+async fn send_placeholder(room: &matrix_sdk::Room, trigger: &OriginalSyncRoomMessageEvent) -> Option<OwnedEventId> {
+	let settings = room_settings::RoomSettings::get(room.room_id());
+	let content = RoomMessageEventContent::notice_plain(format!("⏳ {}", i18n::tr(settings.language, "fetching")));
+	let content = reply_to_trigger(content, trigger, settings.reply_style);
+	match room.send(content).await {
+		Ok(sent) => Some(sent.response.event_id),
+		Err(e) => {
+			println!("  failed to send progress message: {e:?}");
+			None
+		},
+	}
 }
 
 #[derive(Debug, Default, Clone)]
@@ -72,6 +255,9 @@ struct Post {
 	body_plain: String,
 	body_html: String,
 	media: Vec<Media>,
+	sensitive: bool,
+	/// Latitude & longitude.
+	location: Option<(f64, f64)>,
 }
 
 #[derive(Debug, Clone)]
@@ -79,153 +265,478 @@ struct Media {
 	is_video: bool,
 	url: Url,
 	thumbnail_url: Option<Url>,
+	duration: Option<Duration>,
+	/// Tried in order if `url` can't be downloaded.
+	fallback_urls: Vec<Url>,
+}
+
+impl Media {
+	fn new(url: Url, is_video: bool) -> Media {
+		Media {
+			is_video,
+			url,
+			thumbnail_url: None,
+			duration: None,
+			fallback_urls: vec![],
+		}
+	}
+
+	fn with_thumbnail(self, thumbnail_url: Option<Url>) -> Media {
+		Media { thumbnail_url, ..self }
+	}
+
+	fn with_duration(self, duration: Duration) -> Media {
+		Media {
+			duration: Some(duration),
+			..self
+		}
+	}
+
+	fn with_fallbacks(self, fallback_urls: Vec<Url>) -> Media {
+		Media { fallback_urls, ..self }
+	}
+}
+
+// Events are capped at 65535 bytes and encryption base64's everything so keep plain + html well under that.
+const MAX_BODY_LEN: usize = 20_000;
+
+/// Cuts `s` down to at most `max_len` bytes (on a char boundary), " […]" included if anything was cut.
+fn truncate_message(s: &str, max_len: usize) -> String {
+	const SUFFIX: &str = " […]";
+	if s.len() <= max_len {
+		return s.to_owned();
+	}
+	let mut end = max_len.saturating_sub(SUFFIX.len());
+	while !s.is_char_boundary(end) {
+		end -= 1;
+	}
+	format!("{}{SUFFIX}", s.split_at(end).0)
 }
 
 impl Post {
-	async fn send(self, room: &matrix_sdk::Room) -> anyhow::Result<()> {
-		let task_post = tokio::spawn({
+	/// Returns how many media files were uploaded (and how long that took). The text is sent as a reply to `trigger` if there is one.
+	/// `placeholder` is the `!fx progress` message, which the text replaces (or which goes away if there's no text).
+	/// It's taken once that has happened so the caller knows whether it's still around if this fails or gets cancelled.
+	async fn send(
+		self,
+		room: &matrix_sdk::Room,
+		trigger: Option<&OriginalSyncRoomMessageEvent>,
+		placeholder: &mut Option<OwnedEventId>,
+	) -> anyhow::Result<(usize, MediaTimings)> {
+		let settings = room_settings::RoomSettings::get(room.room_id());
+
+		let (media, spoiler) = match (self.sensitive, settings.sensitive) {
+			(true, SensitiveMode::Skip) => (vec![], false),
+			(true, SensitiveMode::Spoiler) => (self.media, true),
+			_ => (self.media, false),
+		};
+
+		// Still send the text when echo is off if there's nothing else to show for the link.
+		let echo = settings.echo == Toggle::On || media.is_empty();
+		let compact = settings.format == Format::Compact && !media.is_empty();
+		let mut body_plain = truncate_message(&self.body_plain, MAX_BODY_LEN);
+		// Cutting HTML could leave half a tag or entity so it's dropped instead (the plain text still goes out).
+		let mut body_html = (self.body_html.len() <= MAX_BODY_LEN).then_some(self.body_html);
+		if settings.reply_style == ReplyStyle::Mention
+			&& let Some(trigger) = trigger
+		{
+			let sender = &trigger.sender;
+			body_plain.insert_str(0, &format!("{sender}: "));
+			if let Some(body_html) = &mut body_html {
+				body_html.insert_str(
+					0,
+					&format!(
+						"<a href=\"{}\">{}</a>: ",
+						sender.matrix_to_uri(),
+						htmlize::escape_text(sender.as_str())
+					),
+				);
+			}
+		}
+
+		let caption = (echo && compact).then(|| match &body_html {
+			Some(body_html) => TextMessageEventContent::html(body_plain.clone(), body_html.clone()),
+			None => TextMessageEventContent::plain(body_plain.clone()),
+		});
+		let echo_text = echo && !compact;
+		let task_post = echo_text.then(|| {
 			let room = room.clone();
-			async move {
-				room.send(RoomMessageEventContent::text_html(self.body_plain, self.body_html))
-					.await
+			let mut content = match (settings.msgtype, body_html) {
+				(Msgtype::Text, Some(body_html)) => RoomMessageEventContent::text_html(body_plain, body_html),
+				(Msgtype::Text, None) => RoomMessageEventContent::text_plain(body_plain),
+				(Msgtype::Notice, Some(body_html)) => RoomMessageEventContent::notice_html(body_plain, body_html),
+				(Msgtype::Notice, None) => RoomMessageEventContent::notice_plain(body_plain),
+			};
+			if let Some(placeholder) = placeholder.take() {
+				content = content.make_replacement(ReplacementMetadata::new(placeholder, None));
+			} else if let Some(trigger) = trigger {
+				content = reply_to_trigger(content, trigger, settings.reply_style);
 			}
+			tokio::spawn(async move { room.send_queue().send(content.into()).await })
 		});
 
 		let task_media = tokio::spawn({
 			let room = room.clone();
-			async move { fetch_and_send_media(room, self.media).await }
+			let reply_to = trigger.map(|trigger| trigger.event_id.clone());
+			let reply_style = settings.reply_style;
+			async move { fetch_and_send_media(room, media, spoiler, caption, reply_to, reply_style).await }
 		});
 
-		let te = task_post.await.unwrap().context("Failed to send post");
+		let te = match task_post {
+			Some(task_post) => task_post.await.unwrap().context("Failed to queue post").map(drop),
+			None => Ok(()),
+		};
 		let tm = task_media.await.unwrap();
+		if !echo_text && let Some(placeholder) = placeholder.take() {
+			let _ = room.redact(&placeholder, None, None).await;
+		}
 		te?;
-		tm?;
+		let tm = tm?;
+
+		if settings.location == Toggle::On
+			&& let Some((latitude, longitude)) = self.location
+		{
+			let mut content = RoomMessageEventContent::new(MessageType::Location(LocationMessageEventContent::new(
+				format!("📍 {latitude}, {longitude}"),
+				format!("geo:{latitude},{longitude}"),
+			)));
+			if let Some(trigger) = trigger {
+				content = reply_to_trigger(content, trigger, settings.reply_style);
+			}
+			// The post itself already went out so this isn't worth an error reply.
+			if let Err(e) = room.send(content).await {
+				println!("  failed to send location: {e:?}");
+			}
+		}
 
-		Ok(())
+		Ok(tm)
 	}
 }
 
-async fn fetch_and_send_media(room: matrix_sdk::Room, media: Vec<Media>) -> anyhow::Result<()> {
-	for media in media {
-		let mut filename = media.url.path_segments().unwrap().last().unwrap().to_owned();
+/// Where `fetch_and_send_media()` spent its time, summed over every file. Shown by `!fx test`.
+#[derive(Debug, Default)]
+struct MediaTimings {
+	/// Downloading the file and its thumbnail (in parallel).
+	fetch: Duration,
+	transcode: Duration,
+	upload: Duration,
+}
 
-		// TODO: grab content-type from this...
-		let task_data = tokio::spawn({
-			let media_url = media.url.clone();
-			async move {
-				println!("  fetching & uploading {}", media_url);
-				HTTP.get(media_url.clone())
+/// Videos go to disk first since they can be huge and might only be needed by ffmpeg.
+enum Download {
+	Memory(Vec<u8>),
+	Disk(tempfile::NamedTempFile),
+}
+
+async fn download_to_file(mut response: reqwest::Response) -> anyhow::Result<tempfile::NamedTempFile> {
+	let file = tempfile::NamedTempFile::new()?;
+	let mut writer = tokio::fs::File::from_std(file.reopen()?);
+	while let Some(chunk) = response.chunk().await.context("Failed to read body of main file")? {
+		writer.write_all(&chunk).await?;
+	}
+	writer.flush().await?;
+	Ok(file)
+}
+
+async fn fetch_media(media_url: Url, is_video: bool) -> anyhow::Result<Download> {
+	println!("  fetching & uploading {media_url}");
+	let response = HTTP
+		.get(media_url)
+		.send()
+		.await
+		.context("Failed to GET main file")?
+		.error_for_status()
+		.context("Bad status")?;
+	if is_video {
+		download_to_file(response).await.map(Download::Disk)
+	} else {
+		let data = response.bytes().await.context("Failed to read entire body of main file")?;
+		Ok(Download::Memory(data.to_vec()))
+	}
+}
+
+fn mime_from_extension(filename: &str) -> Option<mime::Mime> {
+	let (_, ext) = filename.rsplit_once('.')?;
+	match ext.to_ascii_lowercase().as_str() {
+		"jpg" | "jpeg" => Some(mime::IMAGE_JPEG),
+		"png" => Some(mime::IMAGE_PNG),
+		"gif" => Some(mime::IMAGE_GIF),
+		"webp" => Some("image/webp".parse().unwrap()),
+		_ => None,
+	}
+}
+
+/// An attachment that's downloaded & ready to send, minus the caption and reply.
+struct PreparedMedia {
+	url: Url,
+	filename: String,
+	content_type: mime::Mime,
+	data: Vec<u8>,
+	attachment_config: AttachmentConfig,
+	timings: MediaTimings,
+}
+
+/// Downloads (and transcodes or downscales if needed) one file. `None` if it turned out to be something we can't post.
+async fn prepare_media(
+	room: matrix_sdk::Room,
+	media: Media,
+	spoiler: bool,
+	max_width: Option<u32>,
+	max_height: Option<u32>,
+) -> anyhow::Result<Option<PreparedMedia>> {
+	let mut timings = MediaTimings::default();
+	let started = tokio::time::Instant::now();
+	// TODO: grab content-type from this...
+	let task_data = tokio::spawn({
+		let mut urls = std::iter::once(media.url.clone())
+			.chain(media.fallback_urls.clone())
+			.peekable();
+		let is_video = media.is_video;
+		async move {
+			loop {
+				let media_url = urls.next().unwrap();
+				let result = fetch_media(media_url.clone(), is_video).await;
+				match (result, urls.peek()) {
+					(Err(e), Some(next)) => println!("  failed to fetch {media_url} ({e:#}), falling back to {next}"),
+					(result, _) => break result.map(|data| (media_url, data)),
+				}
+			}
+		}
+	});
+
+	let task_thumbnail: tokio::task::JoinHandle<anyhow::Result<Option<Thumbnail>>> = tokio::spawn({
+		let thumbnail_url = media.thumbnail_url.clone();
+		async move {
+			if let Some(thumbnail_url) = thumbnail_url {
+				println!("  fetching thumbnail {thumbnail_url}");
+				let thumbnail_data = HTTP
+					.get(thumbnail_url)
 					.send()
 					.await
-					.context("Failed to GET main file")?
+					.context("Failed to GET thumbnail")?
 					.error_for_status()
 					.context("Bad status")?
 					.bytes()
 					.await
-					.context("Failed to read entire body of main file")
+					.context("Failed to read entire body of thumbnail")?;
+				let thumbnail_size = thumbnail_data.len();
+				let (w, h, content_type) = imageinfo::ImageInfo::from_raw_data(&thumbnail_data)
+					.map(|info| (info.size.width, info.size.height, info.mimetype))
+					.unwrap_or_default();
+				let thumbnail = Thumbnail {
+					data: thumbnail_data.to_vec(),
+					content_type: if content_type.is_empty() {
+						mime::IMAGE_JPEG
+					} else {
+						mime::Mime::from_str(content_type)?
+					},
+					height: (h as u32).into(),
+					width: (w as u32).into(),
+					size: (thumbnail_size as u32).into(),
+				};
+				Ok(Some(thumbnail))
+			} else {
+				Ok(None)
 			}
-		});
+		}
+	});
 
-		let task_thumbnail: tokio::task::JoinHandle<anyhow::Result<Option<Thumbnail>>> = tokio::spawn({
-			let thumbnail_url = media.thumbnail_url.clone();
-			async move {
-				if let Some(thumbnail_url) = thumbnail_url {
-					println!("  fetching thumbnail {thumbnail_url}");
-					let thumbnail_data = HTTP
-						.get(thumbnail_url)
-						.send()
-						.await
-						.context("Failed to GET thumbnail")?
-						.error_for_status()
-						.context("Bad status")?
-						.bytes()
-						.await
-						.context("Failed to read entire body of thumbnail")?;
-					let thumbnail_size = thumbnail_data.len();
-					let (w, h, content_type) = imageinfo::ImageInfo::from_raw_data(&thumbnail_data)
-						.map(|info| (info.size.width, info.size.height, info.mimetype))
-						.unwrap_or_default();
-					let thumbnail = Thumbnail {
-						data: thumbnail_data.to_vec(),
-						content_type: if content_type.is_empty() {
-							mime::IMAGE_JPEG
-						} else {
-							mime::Mime::from_str(content_type)?
-						},
-						height: (h as u32).into(),
-						width: (w as u32).into(),
-						size: (thumbnail_size as u32).into(),
-					};
-					Ok(Some(thumbnail))
-				} else {
-					Ok(None)
+	/*
+	let encrypted_file = client
+		.upload_encrypted_file(&mut std::io::Cursor::new(&data))
+		.with_request_config(RequestConfig::short_retry())
+		.await
+		.context("Failed to upload media")?;
+	println!("  uploaded {}", upload_info.url);
+
+	let encrypted_file_url = encrypted_file.url.as_str();
+	let file_html = if upload_info.filename.ends_with(".mp4") {
+		format!(r##"<video controls><source src="{encrypted_file_url}" /></video>"##)
+	} else {
+		format!(r##"<img src="{encrypted_file_url}">"##)
+	};
+	*/
+
+	let (media_url, data) = task_data.await.unwrap()?;
+	let thumbnail = task_thumbnail.await.unwrap();
+	timings.fetch += started.elapsed();
+	let mut filename = media_url.path_segments().unwrap().last().unwrap().to_owned();
+	if spoiler {
+		filename.insert_str(0, "SPOILER_");
+	}
+
+	let data = match data {
+		Download::Memory(data) => data,
+		Download::Disk(file) => {
+			let size = file.as_file().metadata()?.len();
+			let max_upload_size = u64::from(room.client().load_or_fetch_max_upload_size().await?);
+			if size > max_upload_size {
+				println!("  {media_url} is {size} bytes (max {max_upload_size}), transcoding");
+				if let Some(name) = filename.strip_suffix(".webm") {
+					filename = format!("{name}.mp4");
 				}
+				let started = tokio::time::Instant::now();
+				let data = transcode::transcode_video(file.path(), max_upload_size)
+					.await
+					.context("Failed to transcode oversized video")?;
+				timings.transcode += started.elapsed();
+				data
+			} else {
+				tokio::fs::read(file.path())
+					.await
+					.context("Failed to read downloaded video")?
 			}
-		});
+		},
+	};
+	let data = if !media.is_video && (max_width.is_some() || max_height.is_some()) {
+		let (max_width, max_height) = (max_width.unwrap_or(u32::MAX), max_height.unwrap_or(u32::MAX));
+		let (data, resized) = tokio::task::spawn_blocking(move || {
+			let resized = resize::downscale(&data, max_width, max_height);
+			(data, resized)
+		})
+		.await
+		.unwrap();
+		match resized {
+			Ok(Some(resized)) => {
+				println!("  downscaled {media_url} to fit {max_width}x{max_height}");
+				resized
+			},
+			Ok(None) => data,
+			Err(e) => {
+				println!("  failed to downscale {media_url}, uploading it as-is: {e:?}");
+				data
+			},
+		}
+	} else {
+		data
+	};
 
-		/*
-		let encrypted_file = client
-			.upload_encrypted_file(&mut std::io::Cursor::new(&data))
-			.with_request_config(RequestConfig::short_retry())
-			.await
-			.context("Failed to upload media")?;
-		println!("  uploaded {}", upload_info.url);
+	let mut attachment_config = AttachmentConfig::new();
+	let content_type;
+
+	if media.is_video {
+		attachment_config.info = Some(matrix_sdk::attachment::AttachmentInfo::Video(BaseVideoInfo {
+			duration: media.duration,
+			size: Some((data.len() as u32).into()),
+			..Default::default()
+		}));
 
-		let encrypted_file_url = encrypted_file.url.as_str();
-		let file_html = if upload_info.filename.ends_with(".mp4") {
-			format!(r##"<video controls><source src="{encrypted_file_url}" /></video>"##)
+		// TODO:
+		content_type = if filename.ends_with(".webm") {
+			mime::Mime::from_str("video/webm")?
 		} else {
-			format!(r##"<img src="{encrypted_file_url}">"##)
+			mime::Mime::from_str("video/mp4")?
 		};
-		*/
+	} else if let Ok(info) = imageinfo::ImageInfo::from_raw_data(&data) {
+		if !filename.ends_with(info.ext) {
+			filename.push('.');
+			filename.push_str(info.ext);
+		}
+		attachment_config.info = Some(matrix_sdk::attachment::AttachmentInfo::Image(BaseImageInfo {
+			height: Some((info.size.height as u32).into()),
+			width: Some((info.size.width as u32).into()),
+			size: Some((data.len() as u32).into()),
+			blurhash: None,
+			is_animated: if filename.ends_with(".gif") { Some(true) } else { None },
+		}));
+		content_type = mime::Mime::from_str(info.mimetype)?;
+	} else if let Some(guess) = mime_from_extension(&filename) {
+		println!("  couldn't parse image header, guessing {guess} from {filename}");
+		content_type = guess;
+	} else {
+		// TODO: ?????
+		return Ok(None);
+	}
 
-		let data = task_data.await.unwrap()?;
-		let mut attachment_config = AttachmentConfig::new();
-		let content_type;
+	match thumbnail {
+		Ok(Some(thumbnail)) => {
+			attachment_config = attachment_config.thumbnail(Some(thumbnail));
+		},
+		Ok(None) => (),
+		Err(e) => {
+			println!("  failed to fetch thumbnail {}: {e:?}", media.thumbnail_url.unwrap());
+		},
+	}
 
-		if media.is_video {
-			// TODO:
-			content_type = if filename.ends_with(".webm") {
-				mime::Mime::from_str("video/webm")?
-			} else {
-				mime::Mime::from_str("video/mp4")?
-			};
-		} else if let Ok(info) = imageinfo::ImageInfo::from_raw_data(&data) {
-			if !filename.ends_with(info.ext) {
-				filename.push('.');
-				filename.push_str(info.ext);
+	Ok(Some(PreparedMedia {
+		url: media.url,
+		filename,
+		content_type,
+		data,
+		attachment_config,
+		timings,
+	}))
+}
+
+/// `caption` goes on the first attachment that's sent, which also replies to `reply_to`.
+/// With `ReplyStyle::Thread` every attachment goes in the thread instead.
+async fn fetch_and_send_media(
+	room: matrix_sdk::Room,
+	media: Vec<Media>,
+	spoiler: bool,
+	mut caption: Option<TextMessageEventContent>,
+	reply_to: Option<OwnedEventId>,
+	reply_style: ReplyStyle,
+) -> anyhow::Result<(usize, MediaTimings)> {
+	let settings = room_settings::RoomSettings::get(room.room_id());
+	let max_width = settings.max_media_width.get();
+	let max_height = settings.max_media_height.get();
+	let thumbnails = settings.thumbnails;
+	let mut uploaded = 0;
+	let mut timings = MediaTimings::default();
+
+	// Files are downloaded in parallel but still sent in order.
+	let semaphore = Arc::new(tokio::sync::Semaphore::new(ARGS.max_concurrent_media));
+	let tasks: Vec<_> = media
+		.into_iter()
+		.map(|mut media| {
+			if thumbnails == Toggle::Off {
+				media.thumbnail_url = None;
 			}
-			attachment_config.info = Some(matrix_sdk::attachment::AttachmentInfo::Image(BaseImageInfo {
-				height: Some((info.size.height as u32).into()),
-				width: Some((info.size.width as u32).into()),
-				size: Some((data.len() as u32).into()),
-				blurhash: None,
-				is_animated: if filename.ends_with(".gif") { Some(true) } else { None },
-			}));
-			content_type = mime::Mime::from_str(info.mimetype)?;
-		} else {
-			// TODO: ?????
-			continue;
-		}
+			let room = room.clone();
+			let semaphore = semaphore.clone();
+			AbortOnDropHandle::new(tokio::spawn(async move {
+				let _permit = semaphore.acquire_owned().await.unwrap();
+				prepare_media(room, media, spoiler, max_width, max_height).await
+			}))
+		})
+		.collect();
 
-		match task_thumbnail.await.unwrap() {
-			Ok(Some(thumbnail)) => {
-				attachment_config = attachment_config.thumbnail(Some(thumbnail));
-			},
-			Ok(None) => (),
-			Err(e) => {
-				println!("  failed to fetch thumbnail {}: {e:?}", media.thumbnail_url.unwrap());
-			},
+	for task in tasks {
+		let Some(prepared) = task.await.unwrap()? else {
+			continue;
+		};
+		let mut attachment_config = prepared.attachment_config;
+		timings.fetch += prepared.timings.fetch;
+		timings.transcode += prepared.timings.transcode;
+
+		let caption = caption.take();
+		if caption.is_some() || reply_style == ReplyStyle::Thread {
+			attachment_config = attachment_config.reply(reply_to.clone().map(|event_id| Reply {
+				event_id,
+				enforce_thread: match reply_style {
+					ReplyStyle::Thread => EnforceThread::Threaded(ReplyWithinThread::No),
+					ReplyStyle::None | ReplyStyle::Mention => EnforceThread::Unthreaded,
+				},
+				add_mentions: match reply_style {
+					ReplyStyle::Mention => AddMentions::Yes,
+					ReplyStyle::None | ReplyStyle::Thread => AddMentions::No,
+				},
+			}));
 		}
+		attachment_config = attachment_config.caption(caption);
 
+		let started = tokio::time::Instant::now();
 		let _ = room
-			.send_attachment(filename, &content_type, data.into(), attachment_config)
+			.send_attachment(prepared.filename, &prepared.content_type, prepared.data, attachment_config)
 			.await
 			.context("Failed to send attachment")?;
-		println!("  uploaded {}", media.url);
+		timings.upload += started.elapsed();
+		println!("  uploaded {}", prepared.url);
+		uploaded += 1;
 	}
 
-	Ok(())
+	Ok((uploaded, timings))
 }
 
 #[global_allocator]
@@ -236,10 +747,75 @@ static GLOBAL_ALLOCATOR: mimalloc::MiMalloc = mimalloc::MiMalloc;
 struct Args {
 	#[arg(long)]
 	database_dir: PathBuf,
+	/// Octal mode the database directory is created with by `login`.
+	#[arg(long, default_value = "700", value_parser = parse_octal_mode)]
+	db_dir_permissions: u32,
+	/// Also write the bot's output & matrix-sdk's logs to this file as JSON, rotated daily (the date gets appended to the name).
+	/// matrix-sdk's logs are shown in the terminal too. Filtered with `RUST_LOG` (default `info`).
+	#[arg(long)]
+	log_file: Option<PathBuf>,
 	#[arg(long)]
 	proxy: Option<Url>,
+	/// HTTP version for fetching posts & media. `auto` lets TLS (ALPN) pick, `2` assumes every host speaks it.
+	#[arg(long, value_enum, default_value_t = HttpVersion::Auto)]
+	http_version: HttpVersion,
 	#[arg(long, short)]
 	invite_pattern_to_accept: Option<String>,
+	/// Users allowed to run bot-wide commands like `!die`. Can be repeated.
+	#[arg(long)]
+	admin_user: Vec<OwnedUserId>,
+	/// Don't show the typing indicator while posts are being fetched.
+	#[arg(long)]
+	no_typing: bool,
+	/// Environment variable holding the passphrase for the matrix-sdk store.
+	#[arg(long, conflicts_with = "db_passphrase_file")]
+	db_passphrase_env: Option<String>,
+	/// File holding the passphrase for the matrix-sdk store (like a Docker secret).
+	#[arg(long)]
+	db_passphrase_file: Option<PathBuf>,
+	/// Seconds to spend fetching & posting a single link before giving up on it.
+	#[arg(long, default_value_t = 90)]
+	post_timeout: u64,
+	/// Decline invites once the bot is in this many rooms.
+	#[arg(long)]
+	max_rooms: Option<usize>,
+	/// Port to accept `POST`ed links on (see webhook.rs).
+	#[arg(long, requires = "webhook_secret_file")]
+	webhook_port: Option<u16>,
+	/// Address for the webhook to listen on. Only localhost by default since requests are plain HTTP.
+	#[arg(long, default_value = "127.0.0.1")]
+	webhook_bind: std::net::IpAddr,
+	/// File holding the secret webhook requests need in their `Authorization` header.
+	#[arg(long)]
+	webhook_secret_file: Option<PathBuf>,
+	/// Fetch links from a nitter instance through another fxtwitter-compatible API, like
+	/// `nitter.example.com=api.example.com`. Can be repeated. Other links use the room's `!fx api`.
+	#[arg(long, value_parser = twitter::parse_nitter_api)]
+	nitter_api: Vec<(String, room_settings::ApiEndpoint)>,
+	/// User agent for fetching posts & media instead of the default browser one.
+	#[arg(long, value_parser = clap::builder::NonEmptyStringValueParser::new(), conflicts_with = "user_agent_pkg")]
+	user_agent: Option<String>,
+	/// Use `matrix-fx-bot/<version> (<repository>)` as the user agent.
+	#[arg(long)]
+	user_agent_pkg: bool,
+	/// Only post tweets whose full URL matches this regex. Can be repeated to allow any of them.
+	#[arg(long, value_parser = regex::Regex::new)]
+	filter_regex: Vec<regex::Regex>,
+	/// How many files from one post to download at the same time.
+	#[arg(long, default_value_t = 3, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+	max_concurrent_media: usize,
+	/// Most messages `!fx purge last N` will redact at once.
+	#[arg(long, default_value_t = 100)]
+	purge_limit: usize,
+	/// Room (`#alias:server` or `!id:server`) to tell about invites the bot gets.
+	#[arg(long)]
+	admin_room: Option<OwnedRoomOrAliasId>,
+	/// Also respond in unencrypted rooms (where link previews usually work already).
+	#[arg(long)]
+	allow_unencrypted: bool,
+	/// Only respond in rooms added with `!fx whitelist add`. Admin `!fx` commands work everywhere.
+	#[arg(long)]
+	whitelist_mode: bool,
 	#[command(subcommand)]
 	command: Commands,
 }
@@ -256,19 +832,81 @@ enum Commands {
 		#[arg(long)]
 		login_token: Option<String>,
 	},
-	Run,
+	Run {
+		/// Process whatever was missed since the last run and exit instead of syncing forever (for cron jobs).
+		#[arg(long)]
+		once: bool,
+		/// Address (like `0.0.0.0:8080`) to answer `GET /healthz` on.
+		#[arg(long)]
+		bind_metrics: Option<std::net::SocketAddr>,
+	},
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum HttpVersion {
+	Auto,
+	#[value(name = "1")]
+	Http1,
+	#[value(name = "2")]
+	Http2,
+}
+
+fn parse_octal_mode(s: &str) -> Result<u32, String> {
+	match u32::from_str_radix(s, 8) {
+		Ok(mode) if mode <= 0o7777 => Ok(mode),
+		_ => Err("expected an octal mode like 700".to_owned()),
+	}
+}
+
+/// A browser UA unless `--user-agent`/`--user-agent-pkg` says otherwise since some sites are picky.
+fn user_agent() -> String {
+	if let Some(user_agent) = &ARGS.user_agent {
+		user_agent.clone()
+	} else if ARGS.user_agent_pkg {
+		format!(
+			"{}/{} ({})",
+			env!("CARGO_PKG_NAME"),
+			env!("CARGO_PKG_VERSION"),
+			env!("CARGO_PKG_REPOSITORY")
+		)
+	} else {
+		"Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/147.0.0.0 Safari/537.36".to_owned()
+	}
 }
 
 static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
 static MY_USER_ID: OnceLock<OwnedUserId> = OnceLock::new();
-static SHOULD_DIE: OnceLock<()> = OnceLock::new();
+/// Cancelled by `!die`.
+static SHOULD_DIE: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+// Don't print this anywhere...
+fn db_passphrase() -> anyhow::Result<Option<String>> {
+	if let Some(var) = &ARGS.db_passphrase_env {
+		let passphrase = std::env::var(var).with_context(|| format!("failed to read ${var}"))?;
+		Ok(Some(passphrase))
+	} else if let Some(path) = &ARGS.db_passphrase_file {
+		let passphrase = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+		Ok(Some(passphrase.trim_end().to_owned()))
+	} else {
+		Ok(None)
+	}
+}
+
+fn is_admin(user_id: &UserId) -> bool {
+	ARGS.admin_user.iter().any(|admin| admin == user_id)
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct FxSessionData {
+	version: u32,
 	homeserver: String,
 	user_session: MatrixSession,
 }
 
+// 1: homeserver + user_session
+// 2: added version
+const FX_SESSION_DATA_VERSION: u32 = 2;
+
 impl FxSessionData {
 	// We don't have to persist() after login because sync_with_callback()/sync_once() will store tokens for us in matrix_sdk::ClientBuilder::sqlite_store() files
 	fn persist(&self) -> anyhow::Result<()> {
@@ -299,9 +937,38 @@ impl FxSessionData {
 		let settings = conn.query_one("SELECT settings FROM FxSessionData;", (), |r| {
 			Ok(r.get_ref(0)?.as_str()?.to_owned())
 		})?;
+		// Version 1 didn't store a version...
+		let version = serde_json::from_str::<serde_json::Value>(&settings)?
+			.get("version")
+			.and_then(|v| v.as_u64())
+			.unwrap_or(1) as u32;
+		if version != FX_SESSION_DATA_VERSION {
+			println!("Migrating FxSessionData from version {version} to {FX_SESSION_DATA_VERSION}");
+			let settings = FxSessionData::migrate(&settings, version)?;
+			settings.persist()?;
+			return Ok(settings);
+		}
 		let settings: FxSessionData = serde_json::from_str(&settings)?;
 		Ok(settings)
 	}
+
+	fn migrate(old_json: &str, old_version: u32) -> anyhow::Result<FxSessionData> {
+		anyhow::ensure!(
+			old_version < FX_SESSION_DATA_VERSION,
+			"FxSessionData version {old_version} is from a newer version of the bot"
+		);
+		let mut settings: serde_json::Value = serde_json::from_str(old_json)?;
+		let object = settings.as_object_mut().context("FxSessionData isn't an object")?;
+		for version in old_version..FX_SESSION_DATA_VERSION {
+			match version {
+				1 => {
+					object.insert("version".into(), 2.into());
+				},
+				_ => anyhow::bail!("don't know how to migrate FxSessionData version {version}"),
+			}
+		}
+		Ok(serde_json::from_value(settings)?)
+	}
 }
 
 static HTTP: LazyLock<reqwest_middleware::ClientWithMiddleware> = LazyLock::new(|| {
@@ -309,20 +976,18 @@ static HTTP: LazyLock<reqwest_middleware::ClientWithMiddleware> = LazyLock::new(
 		.connect_timeout(Duration::from_secs(10))
 		.read_timeout(Duration::from_secs(120))
 		.timeout(Duration::from_secs(140))
-		/*
-		.user_agent(format!(
-			"{}/{} ({})",
-			env!("CARGO_PKG_NAME"),
-			env!("CARGO_PKG_VERSION"),
-			env!("CARGO_PKG_REPOSITORY")
-		))
-		*/
-		.user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/147.0.0.0 Safari/537.36");
+		.user_agent(user_agent());
 
 	if let Some(proxy) = &ARGS.proxy {
 		builder = builder.proxy(reqwest::Proxy::all(proxy.clone()).unwrap());
 	}
 
+	builder = match ARGS.http_version {
+		HttpVersion::Auto => builder,
+		HttpVersion::Http1 => builder.http1_only(),
+		HttpVersion::Http2 => builder.http2_prior_knowledge(),
+	};
+
 	reqwest_middleware::ClientBuilder::new(builder.build().unwrap())
 		.with(reqwest_retry::RetryTransientMiddleware::new_with_policy(
 			reqwest_retry::policies::ExponentialBackoff::builder()
@@ -335,14 +1000,55 @@ static HTTP: LazyLock<reqwest_middleware::ClientWithMiddleware> = LazyLock::new(
 
 static OPENGRAPHERS: LazyLock<RwLock<Vec<String>>> = LazyLock::new(|| Default::default());
 
+/// A missing file is fine (no sites) but a broken one isn't.
+async fn read_opengraphers() -> anyhow::Result<Vec<String>> {
+	let s = match tokio::fs::read_to_string("./session-opengraph.json").await {
+		Ok(s) => s,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => return Err(e).context("Failed to read session-opengraph.json"),
+	};
+	if s.trim().is_empty() {
+		return Ok(vec![]);
+	}
+	serde_json::from_str(&s).context("Failed to parse session-opengraph.json")
+}
+
 fn main() -> anyhow::Result<()> {
 	unsafe {
 		std::env::set_var("RUST_BACKTRACE", "full");
 	}
 
+	// Flushes the log file when dropped so it has to live until the end.
+	let _log_guard = ARGS.log_file.as_deref().map(init_log_file).transpose()?;
+
 	tokio::runtime::Runtime::new()?.block_on(async { tokio::spawn(async_main()).await? })
 }
 
+fn init_log_file(path: &Path) -> anyhow::Result<tracing_appender::non_blocking::WorkerGuard> {
+	let dir = path
+		.parent()
+		.filter(|dir| !dir.as_os_str().is_empty())
+		.unwrap_or(Path::new("."));
+	let file_name = path.file_name().context("--log-file needs a file name")?;
+	let (writer, guard) = tracing_appender::non_blocking(tracing_appender::rolling::daily(dir, file_name));
+	let filter = || {
+		tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+	};
+	let terminal = tracing_subscriber::filter::filter_fn(|meta| !matches!(meta.target(), "stdout" | "stderr"));
+	tracing_subscriber::registry()
+		.with(
+			tracing_subscriber::fmt::layer()
+				.json()
+				.with_writer(writer)
+				.with_filter(filter()),
+		)
+		.with(tracing_subscriber::fmt::layer().with_filter(filter()).with_filter(terminal))
+		.try_init()
+		.map_err(|e| anyhow::anyhow!(e))
+		.context("Failed to set up --log-file")?;
+	Ok(guard)
+}
+
 async fn async_main() -> anyhow::Result<()> {
 	match &ARGS.command {
 		Commands::Login {
@@ -351,10 +1057,23 @@ async fn async_main() -> anyhow::Result<()> {
 			password,
 			login_token,
 		} => login(&homeserver, &username, &password, &login_token).await,
-		Commands::Run => run().await,
+		Commands::Run { once, bind_metrics } => {
+			if let Some(addr) = bind_metrics {
+				let listener = health::bind(*addr).await?;
+				println!("Health check listening on {addr}");
+				tokio::spawn(health::serve(listener));
+			}
+			run(*once).await
+		},
 	}
 }
 
+/// `matrix-fx-bot v1.2.3 on somebox` so it's obvious which device is which instance in the session list.
+fn device_display_name() -> String {
+	let host = hostname::get().map_or_else(|_| "unknown".to_owned(), |h| h.to_string_lossy().into_owned());
+	format!("{} {} on {host}", env!("CARGO_PKG_NAME"), build_info::GIT_DESCRIBE)
+}
+
 async fn login(
 	homeserver: &str,
 	username: &Option<String>,
@@ -363,11 +1082,14 @@ async fn login(
 ) -> anyhow::Result<()> {
 	let _ = tokio::fs::remove_dir_all(&ARGS.database_dir).await; // Die, fool.
 	tokio::fs::create_dir_all(&ARGS.database_dir).await?; // Live, fool.
+	tokio::fs::set_permissions(&ARGS.database_dir, std::fs::Permissions::from_mode(ARGS.db_dir_permissions)).await?;
+
+	let db_passphrase = db_passphrase()?;
 
 	println!("Connecting to {homeserver}");
 	let matrix_client = matrix_sdk::Client::builder()
 		.server_name_or_homeserver_url(&homeserver)
-		.sqlite_store(&ARGS.database_dir, None)
+		.sqlite_store(&ARGS.database_dir, db_passphrase.as_deref())
 		.build()
 		.await?;
 	let matrix_auth: matrix_sdk::authentication::matrix::MatrixAuth = matrix_client.matrix_auth();
@@ -380,13 +1102,15 @@ async fn login(
 		println!("Attempting to login to @{username}:{homeserver}");
 		let _response = matrix_auth
 			.login_username(&username, &password)
-			.initial_device_display_name(&format!("Element {}", rand::rng().next_u32() & 255))
+			.initial_device_display_name(&device_display_name())
+			.request_refresh_token()
 			.await?;
 	} else if let Some(login_token) = login_token {
 		println!("Attempting to login with token {login_token}");
 		let _response = matrix_auth
 			.login_token(&login_token)
-			.initial_device_display_name(&format!("Element {}", rand::rng().next_u32() & 255))
+			.initial_device_display_name(&device_display_name())
+			.request_refresh_token()
 			.await?;
 	} else {
 		println!("{:?}", login_types);
@@ -395,6 +1119,7 @@ async fn login(
 
 	let matrix_session = matrix_auth.session().context("matrix_auth.session()")?;
 	FxSessionData {
+		version: FX_SESSION_DATA_VERSION,
 		homeserver: homeserver.to_owned(),
 		user_session: matrix_session,
 	}
@@ -403,23 +1128,118 @@ async fn login(
 	Ok(())
 }
 
-async fn run() -> anyhow::Result<()> {
-	while let Err(e) = run_session_once().await {
+async fn restore_client() -> anyhow::Result<(matrix_sdk::Client, FxSessionData)> {
+	let fx_session_data = FxSessionData::load()?;
+	let db_passphrase = db_passphrase()?;
+	let mut matrix_client_builder = matrix_sdk::Client::builder()
+		.server_name_or_homeserver_url(&fx_session_data.homeserver)
+		.sqlite_store(&ARGS.database_dir, db_passphrase.as_deref());
+
+	if let Some(proxy) = &ARGS.proxy {
+		matrix_client_builder = matrix_client_builder.proxy(proxy);
+	}
+
+	let matrix_client = matrix_client_builder.build().await?;
+
+	matrix_client.restore_session(fx_session_data.user_session.clone()).await?;
+
+	Ok((matrix_client, fx_session_data))
+}
+
+/// Why the homeserver said our access token is no good.
+enum AuthFailure {
+	/// Probably just expired. Worth trying the refresh token.
+	Expired,
+	/// Logged out (like after a password change) so only a new login helps.
+	Revoked,
+}
+
+fn auth_failure(e: &anyhow::Error) -> Option<AuthFailure> {
+	e.chain().find_map(|cause| {
+		let api_error = if let Some(e) = cause.downcast_ref::<matrix_sdk::Error>() {
+			e.as_client_api_error()
+		} else if let Some(e) = cause.downcast_ref::<matrix_sdk::HttpError>() {
+			e.as_client_api_error()
+		} else {
+			None
+		}?;
+		if api_error.status_code != reqwest::StatusCode::UNAUTHORIZED {
+			return None;
+		}
+		Some(match api_error.error_kind() {
+			Some(ErrorKind::UnknownToken(UnknownTokenErrorData { soft_logout: false, .. })) => AuthFailure::Revoked,
+			_ => AuthFailure::Expired,
+		})
+	})
+}
+
+/// Trades the refresh token for a new access token and saves it so the next `run_session_once()` picks it up.
+async fn refresh_session() -> anyhow::Result<()> {
+	let (matrix_client, mut fx_session_data) = restore_client().await?;
+	matrix_client.matrix_auth().refresh_access_token().await?;
+	fx_session_data.user_session = matrix_client.matrix_auth().session().context("matrix_auth.session()")?;
+	fx_session_data.persist()
+}
+
+async fn run(once: bool) -> anyhow::Result<()> {
+	const MAX_DELAY: u64 = 300;
+	let mut delay = 2;
+
+	if once {
+		// Whatever's running us can retry.
+		return run_session_once(true).await;
+	}
+
+	loop {
+		let started = tokio::time::Instant::now();
+		let Err(e) = run_session_once(false).await else {
+			break;
+		};
 		println!("{e:?}");
-		println!("Restarting in 10s");
-		tokio::time::sleep(Duration::from_secs(10)).await;
+
+		if let Some(failure) = auth_failure(&e) {
+			let refreshed = matches!(failure, AuthFailure::Expired) && {
+				println!("Access token was rejected. Trying to refresh it...");
+				match refresh_session().await {
+					Ok(()) => {
+						println!("Refreshed the access token");
+						true
+					},
+					Err(refresh_e) => {
+						println!("Failed to refresh the access token: {refresh_e:?}");
+						false
+					},
+				}
+			};
+			if !refreshed {
+				// Restarting would just fail the same way forever.
+				println!("Session revoked — please re-login with the `login` subcommand");
+				SHOULD_DIE.cancel();
+				return Err(e);
+			}
+			// Still goes through the backoff below in case the server keeps rejecting the new token too.
+		}
+
+		// If the session was up for a while then it was syncing fine and this is a new problem.
+		if started.elapsed() > Duration::from_secs(MAX_DELAY) {
+			delay = 2;
+		}
+
+		println!("Restarting in {delay}s");
+		tokio::select! {
+			() = tokio::time::sleep(Duration::from_secs(delay)) => (),
+			() = SHOULD_DIE.cancelled() => break,
+		}
+		delay = (delay * 2).min(MAX_DELAY);
 	}
+
 	Ok(())
 }
 
-async fn run_session_once() -> anyhow::Result<()> {
+/// `catch_up_and_exit` handles the events since the last sync (instead of skipping them) and returns once there's nothing new.
+async fn run_session_once(catch_up_and_exit: bool) -> anyhow::Result<()> {
 	{
-		let v: Vec<String> = serde_json::from_str(
-			&tokio::fs::read_to_string("./session-opengraph.json")
-				.await
-				.unwrap_or_default(),
-		)
-		.unwrap_or_default();
+		let v = read_opengraphers().await.unwrap_or_default();
 		if !v.is_empty() {
 			println!("opengraph sites:");
 			for site in &v {
@@ -429,25 +1249,15 @@ async fn run_session_once() -> anyhow::Result<()> {
 		*OPENGRAPHERS.write().unwrap() = v;
 	}
 
-	let fx_session_data = FxSessionData::load()?;
-	let mut matrix_client_builder = matrix_sdk::Client::builder()
-		.server_name_or_homeserver_url(&fx_session_data.homeserver)
-		.sqlite_store(&ARGS.database_dir, None);
-
-	if let Some(proxy) = &ARGS.proxy {
-		matrix_client_builder = matrix_client_builder.proxy(proxy);
-	}
-
-	let matrix_client = matrix_client_builder.build().await?;
-
-	matrix_client.restore_session(fx_session_data.user_session.clone()).await?;
+	let (matrix_client, _) = restore_client().await?;
 
 	println!("Syncing...");
 
 	let filter = FilterDefinition::with_lazy_loading();
 	let mut sync_settings = SyncSettings::default().filter(filter.into());
 
-	{
+	if !catch_up_and_exit {
+		// Skip everything that happened while we were gone.
 		let response = matrix_client.sync_once(sync_settings.clone()).await?;
 		sync_settings = sync_settings.token(response.next_batch.clone());
 	}
@@ -462,32 +1272,130 @@ async fn run_session_once() -> anyhow::Result<()> {
 
 	MY_USER_ID.get_or_init(|| matrix_client.user_id().unwrap().to_owned());
 
+	// An empty store (first run) would look like we left everything.
+	let joined: HashSet<String> = matrix_client.joined_rooms().iter().map(|r| r.room_id().to_string()).collect();
+	if !joined.is_empty() {
+		match db::forget_rooms_except(&joined) {
+			Ok(0) => (),
+			Ok(removed) => println!("Forgot {removed} rows for rooms we're no longer in"),
+			Err(e) => println!("Failed to clean up old rooms: {e:?}"),
+		}
+	}
+
 	matrix_client.add_event_handler(on_room_message);
 	matrix_client.add_event_handler(on_stripped_state_member);
 
 	verification::register_handlers(&matrix_client);
+	let _send_queue = send_queue::start(&matrix_client).await;
+	let _webhook = match (ARGS.webhook_port, &ARGS.webhook_secret_file) {
+		(Some(port), Some(secret_file)) => {
+			let secret = std::fs::read_to_string(secret_file)
+				.with_context(|| format!("failed to read {}", secret_file.display()))?
+				.trim()
+				.to_owned();
+			anyhow::ensure!(!secret.is_empty(), "{} is empty", secret_file.display());
+			let addr = std::net::SocketAddr::new(ARGS.webhook_bind, port);
+			Some(webhook::start(addr, &matrix_client, secret).await?)
+		},
+		_ => None,
+	};
 
 	println!("max_upload_size = {:?}", matrix_client.load_or_fetch_max_upload_size().await?);
 
-	matrix_client
-		.sync_with_callback(sync_settings, |_| async {
-			if SHOULD_DIE.get().is_some() {
-				matrix_sdk::LoopCtrl::Break
-			} else {
-				matrix_sdk::LoopCtrl::Continue
+	if catch_up_and_exit {
+		// The store remembers the last next_batch so the first sync picks up where the previous run stopped.
+		sync_settings = sync_settings.timeout(Duration::ZERO);
+		loop {
+			let response = matrix_client.sync_once(sync_settings.clone()).await?;
+			sync_settings = sync_settings.token(response.next_batch.clone());
+			if response.rooms.is_empty() || SHOULD_DIE.is_cancelled() {
+				break;
 			}
-		})
-		.await?;
+		}
+		println!("Caught up");
+		return Ok(());
+	}
+
+	// Don't wait for the long-poll to come back before dying.
+	tokio::select! {
+		result = matrix_client.sync(sync_settings) => result?,
+		() = SHOULD_DIE.cancelled() => (),
+	}
 
 	Ok(())
 }
 
+/// Lets clients & other bots see what we do without having to poke us.
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "m.room.bot.options", kind = State, state_key_type = EmptyStateKey)]
+struct BotOptionsEventContent {
+	name: String,
+	version: String,
+	sites: Vec<String>,
+	commands: Vec<String>,
+}
+
+impl BotOptionsEventContent {
+	fn new() -> Self {
+		Self {
+			name: env!("CARGO_PKG_NAME").to_owned(),
+			version: format!("{} ({})", env!("CARGO_PKG_VERSION"), build_info::GIT_DESCRIBE),
+			sites: ["bsky", "misskey", "opengraph", "pixiv", "twitter"].map(String::from).into(),
+			commands: commands::COMMANDS.iter().map(|cmd| format!("!fx {cmd}")).collect(),
+		}
+	}
+}
+
+/// [fx]twitter embeds mostly work in unencrypted rooms so the bot stays out of them unless `--allow-unencrypted` is set.
+fn check_encryption(room: &matrix_sdk::Room) -> bool {
+	let allowed = ARGS.allow_unencrypted || room.encryption_state().is_encrypted();
+	if !allowed {
+		tracing::debug!(room_id = ?room.room_id(), "skipping unencrypted room");
+	}
+	allowed
+}
+
+/// Sends `text` to `--admin-room` if it's set and the bot is in it.
+async fn notify_admin_room(client: &matrix_sdk::Client, text: &str) {
+	let Some(admin_room) = &ARGS.admin_room else {
+		return;
+	};
+	let room_id = match <&RoomAliasId>::try_from(&**admin_room) {
+		Ok(alias) => match client.resolve_room_alias(alias).await {
+			Ok(response) => response.room_id,
+			Err(e) => {
+				println!("Failed to resolve --admin-room {alias}: {e:?}");
+				return;
+			},
+		},
+		Err(room_id) => room_id.to_owned(),
+	};
+	if let Some(room) = client.get_room(&room_id)
+		&& room.state() == RoomState::Joined
+	{
+		let _ = room.send(RoomMessageEventContent::notice_plain(text)).await;
+	}
+}
+
 // copied from https://github.com/matrix-org/matrix-rust-sdk/blob/4257649933dfe61f44f35efd2de5726c2f24aac7/examples/autojoin/src/main.rs#L8
 async fn on_stripped_state_member(room_member: StrippedRoomMemberEvent, client: matrix_sdk::Client, room: matrix_sdk::Room) {
 	if room_member.state_key != client.user_id().unwrap() {
 		return;
 	}
 
+	if room_member.content.membership == MembershipState::Invite {
+		let room_name = room.name().map(|name| format!("{name} ")).unwrap_or_default();
+		notify_admin_room(
+			&client,
+			&format!(
+				"Received invite to {room_name}({}) from {}",
+				room.room_id(),
+				room_member.sender
+			),
+		)
+		.await;
+	}
+
 	let Some(name) = &room.name() else {
 		return;
 	};
@@ -500,6 +1408,23 @@ async fn on_stripped_state_member(room_member: StrippedRoomMemberEvent, client:
 		return;
 	}
 
+	if let Some(max_rooms) = ARGS.max_rooms
+		&& client.joined_rooms().len() >= max_rooms
+	{
+		println!(
+			"Declining invite to {} from {} (max rooms reached)",
+			room.room_id(),
+			room_member.sender
+		);
+		// We can't message a room we haven't joined so the leave reason is the decline message.
+		let mut request = leave_room::v3::Request::new(room.room_id().to_owned());
+		request.reason = Some(format!("Sorry, this bot is already in its limit of {max_rooms} rooms."));
+		if let Err(e) = client.send(request).await {
+			println!("Failed to decline invite to {}: {e:?}", room.room_id());
+		}
+		return;
+	}
+
 	tokio::spawn(async move {
 		println!("Autojoining room {} (invite from {})", room.room_id(), room_member.sender);
 		let mut delay = 2;
@@ -519,36 +1444,179 @@ async fn on_stripped_state_member(room_member: StrippedRoomMemberEvent, client:
 			}
 		}
 		println!("Successfully joined room {}", room.room_id());
+
+		// This needs permission to send state events so don't be surprised when it fails.
+		if let Err(e) = room.send_state_event(BotOptionsEventContent::new()).await {
+			println!("Failed to send m.room.bot.options to {}: {e:?}", room.room_id());
+		}
 	});
 }
 
+/// Fetches the post behind a link (without sending anything).
+async fn get_post(room: &matrix_sdk::Room, target: Target) -> anyhow::Result<Post> {
+	match target {
+		Target::Bsky(url) => bsky::get_post(url).await,
+		Target::Misskey(url) => misskey::get_post(url).await,
+		Target::Opengraph(url) => opengraph::get_post(url).await,
+		Target::Pixiv(url) => pixiv::get_post(url).await,
+		Target::Twitter(url) => {
+			let max_upload_size = room.client().load_or_fetch_max_upload_size().await?;
+			let settings = room_settings::RoomSettings::get(room.room_id());
+			twitter::get_post(url, max_upload_size.into(), &settings).await
+		},
+	}
+}
+
+/// Fetches & posts one link. `trigger` is the message it came from (if any) which gets replied to.
+async fn process_target(room: &matrix_sdk::Room, target: Target, trigger: Option<&OriginalSyncRoomMessageEvent>) {
+	println!("found {target:?}");
+	let mut placeholder = match trigger {
+		Some(trigger) if room_settings::RoomSettings::get(room.room_id()).progress == Toggle::On => {
+			send_placeholder(room, trigger).await
+		},
+		_ => None,
+	};
+	// A slow media download shouldn't hold up the rest of the links forever.
+	let posted = tokio::time::timeout(Duration::from_secs(ARGS.post_timeout), async {
+		let post = get_post(room, target).await?;
+		post.send(room, trigger, &mut placeholder).await
+	})
+	.await;
+	// Still here if the post never got as far as replacing or redacting it (like when the media upload timed out).
+	match posted {
+		Ok(Ok((uploaded, _))) => stats::record(room.room_id(), 1, uploaded as i64, 0),
+		Ok(Err(e)) => {
+			println!("  error: {e:?}");
+			stats::record(room.room_id(), 1, 0, 1);
+			send_error_reply(room, trigger, placeholder, &e).await;
+		},
+		Err(_) => {
+			println!("  error: timed out after {}s", ARGS.post_timeout);
+			stats::record(room.room_id(), 1, 0, 1);
+			let language = room_settings::RoomSettings::get(room.room_id()).language;
+			let content = RoomMessageEventContent::text_plain(i18n::tr(language, "post_timeout"));
+			match placeholder {
+				Some(_) => send_status(room, trigger, placeholder, content).await,
+				None => {
+					let _ = room.send(content).await;
+				},
+			}
+		},
+	}
+}
+
+fn find_targets(body: &str, room_domains: &[String]) -> Vec<Target> {
+	linkify::LinkFinder::new()
+		.links(body)
+		.filter_map(|l| Url::from_str(l.as_str()).ok())
+		.filter(|u| u.scheme() == "https")
+		.filter_map(|u| Target::get(u, room_domains))
+		.unique_by(Target::dedup_key)
+		.collect()
+}
+
+/// The body of a text message from earlier. `None` if it isn't one.
+async fn get_original_event_text(room: &matrix_sdk::Room, event_id: &EventId) -> anyhow::Result<Option<String>> {
+	let event = room.load_or_fetch_event(event_id, None).await?;
+	let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(SyncMessageLikeEvent::Original(message)))) =
+		event.raw().deserialize()
+	else {
+		return Ok(None);
+	};
+	Ok(match message.content.msgtype {
+		MessageType::Text(text) => Some(text.body),
+		_ => None,
+	})
+}
+
+/// The bodies of the text edits of `original_id` (apart from `current`), newest first.
+async fn get_edit_texts(room: &matrix_sdk::Room, original_id: &EventId, current: &EventId) -> anyhow::Result<Vec<String>> {
+	let mut texts = vec![];
+	let mut from = None;
+	loop {
+		let relations = room
+			.relations(original_id.to_owned(), RelationsOptions {
+				from,
+				include_relations: IncludeRelations::RelationsOfType(RelationType::Replacement),
+				..Default::default()
+			})
+			.await?;
+		for event in relations.chunk {
+			let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(SyncMessageLikeEvent::Original(
+				message,
+			)))) = event.raw().deserialize()
+			else {
+				continue;
+			};
+			if message.event_id != current
+				&& let Some(Relation::Replacement(replacement)) = message.content.relates_to
+				&& let MessageType::Text(text) = replacement.new_content.msgtype
+			{
+				texts.push(text.body);
+			}
+		}
+		from = relations.prev_batch_token;
+		if from.is_none() {
+			return Ok(texts);
+		}
+	}
+}
+
+/// Whether the replied-to message is from something in `!fx bot list`.
+async fn replying_to_bot(room: &matrix_sdk::Room, event_id: &EventId) -> bool {
+	let Ok(event) = room.load_or_fetch_event(event_id, None).await else {
+		return false;
+	};
+	let Some(sender) = event.sender() else {
+		return false;
+	};
+	bots::is_bot(&sender).unwrap_or_else(|e| {
+		println!("  failed to check bot accounts: {e:?}");
+		false
+	})
+}
+
 async fn on_room_message(event: OriginalSyncRoomMessageEvent, room: matrix_sdk::Room, _client: matrix_sdk::Client) {
 	if room.state() != RoomState::Joined {
 		return;
 	}
 
 	if event.sender.eq(MY_USER_ID.wait()) {
+		commands::on_own_message(&event);
 		return;
 	}
 
-	if !room.encryption_state().is_encrypted() {
-		// [fx]twitter embeds mostly work in unencrypted rooms so this isn't necessary.
+	if !check_encryption(&room) {
 		return;
 	}
 
-	if let Some(relates_to) = &event.content.relates_to
-		&& let Some(rel_type) = relates_to.rel_type()
-		&& rel_type == RelationType::Replacement
-	{
-		// skip edited messages
+	// Edits only post links that weren't in the message before (and don't run commands).
+	let (body, edited) = match &event.content.relates_to {
+		Some(Relation::Replacement(replacement)) => match &replacement.new_content.msgtype {
+			MessageType::Text(text) => (&text.body, Some(&replacement.event_id)),
+			_ => return,
+		},
+		_ => match &event.content.msgtype {
+			MessageType::Text(text) => (&text.body, None),
+			_ => return,
+		},
+	};
+
+	let admin_command = edited.is_none() && is_admin(&event.sender) && body.trim_start().starts_with("!fx ");
+	if !admin_command && !whitelist::allowed(room.room_id()) {
 		return;
 	}
 
-	let MessageType::Text(text) = &event.content.msgtype else {
+	if let Some(Relation::Reply(reply)) = &event.content.relates_to
+		&& replying_to_bot(&room, &reply.in_reply_to.event_id).await
+	{
 		return;
-	};
+	}
 
-	let (cmd, rest) = text.body.trim().split_once(' ').unwrap_or_default();
+	let (cmd, rest) = match edited {
+		Some(_) => ("", ""),
+		None => body.trim().split_once(' ').unwrap_or_default(),
+	};
 
 	match cmd {
 		"!status" => {
@@ -558,18 +1626,18 @@ async fn on_room_message(event: OriginalSyncRoomMessageEvent, room: matrix_sdk::
 			return;
 		},
 		"!die" => {
-			if let Ok(Some(sender)) = room.get_member(&event.sender).await
-				&& sender.can_kick()
-			{
-				let _ = SHOULD_DIE.set(());
+			if is_admin(&event.sender) {
+				SHOULD_DIE.cancel();
 				println!("!die");
 			}
 			return;
 		},
+		"!fx" => {
+			commands::handle(&event, &room, rest.trim()).await;
+			return;
+		},
 		"!opengraph" => {
-			if let Ok(Some(sender)) = room.get_member(&event.sender).await
-				&& sender.can_kick()
-			{
+			if is_admin(&event.sender) {
 				let rest = rest.to_lowercase();
 				let added;
 				{
@@ -600,52 +1668,128 @@ async fn on_room_message(event: OriginalSyncRoomMessageEvent, room: matrix_sdk::
 		_ => (),
 	}
 
-	let mut targets: Vec<_> = linkify::LinkFinder::new()
-		.links(&text.body)
-		.filter_map(|l| Url::from_str(l.as_str()).ok())
-		.filter(|u| u.scheme() == "https")
-		.filter_map(Target::get)
-		.collect();
+	let room_domains = allowlist::get(room.room_id());
+	let mut targets = find_targets(body, &room_domains);
+
+	if let Some(original_id) = edited
+		&& !targets.is_empty()
+	{
+		let original = match get_original_event_text(&room, original_id).await {
+			Ok(Some(original)) => original,
+			Ok(None) => return,
+			Err(e) => {
+				println!("  failed to load the original of an edit: {e:?}");
+				return;
+			},
+		};
+		// Links added by an earlier edit were posted back then, so every version counts and not just the original.
+		let earlier_edits = match get_edit_texts(&room, original_id, &event.event_id).await {
+			Ok(earlier_edits) => earlier_edits,
+			Err(e) => {
+				println!("  failed to load the earlier edits of an edit: {e:?}");
+				return;
+			},
+		};
+		let already_posted: HashSet<_> = std::iter::once(&original)
+			.chain(&earlier_edits)
+			.flat_map(|body| find_targets(body, &room_domains))
+			.map(|target| target.dedup_key())
+			.collect();
+		targets.retain(|target| !already_posted.contains(&target.dedup_key()));
+	}
 
 	if targets.is_empty() {
 		return;
 	}
+	contributors::record(room.room_id(), &event.sender, targets.len() as i64);
 
-	let typer = tokio::spawn({
-		let room = room.clone();
-		async move {
-			loop {
-				let _ = room.typing_notice(true).await;
-				tokio::time::sleep(Duration::from_secs_f32(1.0)).await;
+	let typer = (!ARGS.no_typing).then(|| {
+		tokio::spawn({
+			let room = room.clone();
+			async move {
+				loop {
+					let _ = room.typing_notice(true).await;
+					tokio::time::sleep(Duration::from_secs_f32(1.0)).await;
+				}
 			}
-		}
+		})
 	});
 
-	targets.dedup();
-
 	for target in targets {
-		println!("found {target:?}");
-		let post = match target {
-			Target::Bsky(url) => bsky::get_post(url).await,
-			Target::Misskey(url) => misskey::get_post(url).await,
-			Target::Opengraph(url) => opengraph::get_post(url).await,
-			Target::Pixiv(url) => pixiv::get_post(url).await,
-			Target::Twitter(url) => twitter::get_post(url).await,
-		};
-		match post {
-			Ok(post) => {
-				if let Err(e) = post.send(&room).await {
-					println!("  error: {e:?}");
-				}
-			},
-			Err(e) => println!("  error: {e:?}"),
-		}
+		process_target(&room, target, Some(&event)).await;
 	}
 
 	// keep typing for a tad longer...
-	tokio::spawn(async move {
-		tokio::time::sleep(Duration::from_secs(1)).await;
-		typer.abort();
-		let _ = typer.await;
-	});
+	if let Some(typer) = typer {
+		tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_secs(1)).await;
+			typer.abort();
+			let _ = typer.await;
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn truncate_message_multibyte() {
+		// `é` is 2 bytes so a 2 byte cut would land in the middle of it.
+		assert_eq!(truncate_message("aé", 3), "aé");
+		assert_eq!(truncate_message("aé", 2), " […]");
+		assert_eq!(truncate_message("aébcdefgh", 2 + " […]".len()), "a […]");
+		let long = "é".repeat(100);
+		let cut = truncate_message(&long, 51);
+		assert!(cut.len() <= 51, "{} bytes", cut.len());
+		assert!(cut.ends_with(" […]"));
+	}
+
+	/// Same as how `prepare_media()` gets the filename.
+	fn mime_for_url(url: &str) -> Option<mime::Mime> {
+		let url = Url::parse(url).unwrap();
+		mime_from_extension(url.path_segments().unwrap().next_back().unwrap())
+	}
+
+	#[test]
+	fn mime_from_extension_table() {
+		let cases = [
+			("https://pbs.twimg.com/media/abc.jpg", Some("image/jpeg")),
+			("https://example.com/a/b.JPEG", Some("image/jpeg")),
+			("https://example.com/image.png?name=orig", Some("image/png")),
+			("https://example.com/image.webp", Some("image/webp")),
+			("https://gif.fxtwitter.com/tweet_video/abc.gif", Some("image/gif")),
+			("https://example.com/video.mp4", None),
+			("https://example.com/no_extension", None),
+		];
+		for (url, expected) in cases {
+			assert_eq!(mime_for_url(url).as_ref().map(mime::Mime::essence_str), expected, "{url}");
+		}
+	}
+
+	const FX_SESSION_DATA_V1: &str = r#"{
+		"homeserver": "https://matrix.example.com",
+		"user_session": {
+			"user_id": "@fx:example.com",
+			"device_id": "ABCDEFGHIJ",
+			"access_token": "syt_access",
+			"refresh_token": "syr_refresh"
+		}
+	}"#;
+
+	#[test]
+	fn fx_session_data_migrate_v1() {
+		let settings = FxSessionData::migrate(FX_SESSION_DATA_V1, 1).unwrap();
+		assert_eq!(settings.version, 2);
+		assert_eq!(settings.homeserver, "https://matrix.example.com");
+		assert_eq!(settings.user_session.meta.user_id, "@fx:example.com");
+		assert_eq!(settings.user_session.meta.device_id, "ABCDEFGHIJ");
+		assert_eq!(settings.user_session.tokens.access_token, "syt_access");
+		assert_eq!(settings.user_session.tokens.refresh_token.as_deref(), Some("syr_refresh"));
+	}
+
+	#[test]
+	fn fx_session_data_migrate_newer_version() {
+		assert!(FxSessionData::migrate(FX_SESSION_DATA_V1, 3).is_err());
+	}
 }
@@ -8,6 +8,7 @@ use serde::Serialize;
 use crate::HTTP;
 
 pub(super) const TARGETS: &[&str] = &["misskey.io"];
+const _: () = assert!(crate::url_util::all_valid_hostnames(TARGETS), "typo in TARGETS");
 
 #[derive(Serialize, Deserialize)]
 struct Properties1 {
@@ -159,11 +160,8 @@ pub(super) async fn get_post(url: Url) -> anyhow::Result<crate::Post> {
 	);
 
 	for media in misskey.files {
-		post.media.push(crate::Media {
-			is_video: media.r#type.contains("video/"),
-			url: media.url,
-			thumbnail_url: Some(media.thumbnailUrl),
-		});
+		post.media
+			.push(crate::Media::new(media.url, media.r#type.contains("video/")).with_thumbnail(Some(media.thumbnailUrl)));
 	}
 
 	Ok(post)
@@ -73,21 +73,14 @@ pub(super) async fn get_post(url: Url) -> anyhow::Result<crate::Post> {
 
 	// TODO: support multiple videos...
 	if let Ok(video) = get_og("og:video") {
-		post.media.push(crate::Media {
-			is_video: true,
-			url: video.parse()?,
-			thumbnail_url: Some(get_og("og:image")?.parse()?),
-		});
+		post.media
+			.push(crate::Media::new(video.parse()?, true).with_thumbnail(Some(get_og("og:image")?.parse()?)));
 	} else {
 		for image in page.select(&Selector::parse(&format!("meta[property=\"og:image\"]")).unwrap()) {
 			let Some(url) = image.attr("content") else {
 				continue;
 			};
-			post.media.push(crate::Media {
-				is_video: false,
-				url: url.parse()?,
-				thumbnail_url: None,
-			});
+			post.media.push(crate::Media::new(url.parse()?, false));
 		}
 	}
 
@@ -14,6 +14,7 @@ pub(super) const TARGETS: &[&str] = &[
 	"ppxiv.net",
 	"www.ppxiv.net",
 ];
+const _: () = assert!(crate::url_util::all_valid_hostnames(TARGETS), "typo in TARGETS");
 
 #[derive(Serialize, Deserialize)]
 struct PhixivResponse {
@@ -132,18 +133,10 @@ pub(super) async fn get_post(mut url: Url) -> anyhow::Result<crate::Post> {
 	);
 
 	if phixiv.image_proxy_urls[0].path().ends_with(".mp4") {
-		post.media.push(crate::Media {
-			is_video: true,
-			url: phixiv.image_proxy_urls[0].clone(),
-			thumbnail_url: None,
-		});
+		post.media.push(crate::Media::new(phixiv.image_proxy_urls[0].clone(), true));
 	} else {
 		for url in phixiv.image_proxy_urls {
-			post.media.push(crate::Media {
-				is_video: false,
-				url: url,
-				thumbnail_url: None,
-			});
+			post.media.push(crate::Media::new(url, false));
 		}
 	}
 
@@ -0,0 +1,22 @@
+/// Scales `data` down to fit in `max_width`x`max_height` (keeping the aspect ratio) and re-encodes it in the same format.
+/// `None` if it already fits. Gifs are left alone since they're usually animated.
+#[cfg(feature = "image-resize")]
+pub(crate) fn downscale(data: &[u8], max_width: u32, max_height: u32) -> anyhow::Result<Option<Vec<u8>>> {
+	let format = image::guess_format(data)?;
+	if format == image::ImageFormat::Gif {
+		return Ok(None);
+	}
+	let img = image::load_from_memory_with_format(data, format)?;
+	if img.width() <= max_width && img.height() <= max_height {
+		return Ok(None);
+	}
+	let img = img.resize(max_width, max_height, image::imageops::FilterType::Lanczos3);
+	let mut out = std::io::Cursor::new(Vec::new());
+	img.write_to(&mut out, format)?;
+	Ok(Some(out.into_inner()))
+}
+
+#[cfg(not(feature = "image-resize"))]
+pub(crate) fn downscale(_data: &[u8], _max_width: u32, _max_height: u32) -> anyhow::Result<Option<Vec<u8>>> {
+	Ok(None)
+}
@@ -0,0 +1,438 @@
+use matrix_sdk::ruma::RoomId;
+use rusqlite::OptionalExtension;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::db;
+use crate::i18n::Language;
+
+/// What to do with media from posts that are marked as sensitive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SensitiveMode {
+	Skip,
+	#[default]
+	Upload,
+	/// Prefix filenames with `SPOILER_`.
+	Spoiler,
+}
+
+impl std::str::FromStr for SensitiveMode {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		match s {
+			"skip" => Ok(SensitiveMode::Skip),
+			"upload" => Ok(SensitiveMode::Upload),
+			"spoiler" => Ok(SensitiveMode::Spoiler),
+			_ => anyhow::bail!("expected one of: skip, upload, spoiler"),
+		}
+	}
+}
+
+impl std::fmt::Display for SensitiveMode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			SensitiveMode::Skip => "skip",
+			SensitiveMode::Upload => "upload",
+			SensitiveMode::Spoiler => "spoiler",
+		})
+	}
+}
+
+/// The msgtype used for the text part of posts. Attachments are always m.image/m.video.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Msgtype {
+	Text,
+	/// Meant for bots. Dimmed in some clients and doesn't ping anyone.
+	#[default]
+	Notice,
+}
+
+impl std::str::FromStr for Msgtype {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		match s {
+			"text" => Ok(Msgtype::Text),
+			"notice" => Ok(Msgtype::Notice),
+			_ => anyhow::bail!("expected one of: text, notice"),
+		}
+	}
+}
+
+impl std::fmt::Display for Msgtype {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Msgtype::Text => "text",
+			Msgtype::Notice => "notice",
+		})
+	}
+}
+
+/// For all the settings that are just on or off.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Toggle {
+	Off,
+	On,
+}
+
+impl std::str::FromStr for Toggle {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		match s {
+			"on" => Ok(Toggle::On),
+			"off" => Ok(Toggle::Off),
+			_ => anyhow::bail!("expected one of: on, off"),
+		}
+	}
+}
+
+impl std::fmt::Display for Toggle {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Toggle::On => "on",
+			Toggle::Off => "off",
+		})
+	}
+}
+
+/// How posts with media are laid out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+	/// A text event followed by the media.
+	#[default]
+	Full,
+	/// The text goes in the first attachment's caption.
+	Compact,
+}
+
+impl std::str::FromStr for Format {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		match s {
+			"full" => Ok(Format::Full),
+			"compact" => Ok(Format::Compact),
+			_ => anyhow::bail!("expected one of: full, compact"),
+		}
+	}
+}
+
+impl std::fmt::Display for Format {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Format::Full => "full",
+			Format::Compact => "compact",
+		})
+	}
+}
+
+/// How posts relate to the message with the link.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ReplyStyle {
+	/// A plain reply.
+	#[default]
+	None,
+	/// Starts (or continues) a thread on the message.
+	Thread,
+	/// A reply that also pings the sender.
+	Mention,
+}
+
+impl std::str::FromStr for ReplyStyle {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		match s {
+			"none" => Ok(ReplyStyle::None),
+			"thread" => Ok(ReplyStyle::Thread),
+			"mention" => Ok(ReplyStyle::Mention),
+			_ => anyhow::bail!("expected one of: thread, mention, none"),
+		}
+	}
+}
+
+impl std::fmt::Display for ReplyStyle {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			ReplyStyle::None => "none",
+			ReplyStyle::Thread => "thread",
+			ReplyStyle::Mention => "mention",
+		})
+	}
+}
+
+/// IANA time zone name used for post timestamps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Timezone(String);
+
+impl Default for Timezone {
+	fn default() -> Self {
+		Timezone("UTC".to_owned())
+	}
+}
+
+impl Timezone {
+	/// Falls back to UTC if the name stopped existing (system tzdb updates can do that).
+	pub(crate) fn get(&self) -> jiff::tz::TimeZone {
+		jiff::tz::TimeZone::get(&self.0).unwrap_or(jiff::tz::TimeZone::UTC)
+	}
+}
+
+impl std::str::FromStr for Timezone {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		match jiff::tz::TimeZone::get(s) {
+			Ok(tz) => Ok(Timezone(tz.iana_name().unwrap_or(s).to_owned())),
+			Err(_) => anyhow::bail!("expected an IANA time zone name like UTC, Europe/Berlin, or America/New_York"),
+		}
+	}
+}
+
+impl std::fmt::Display for Timezone {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+/// Hostname of the fxtwitter-compatible API to use for tweets (like a self-hosted instance).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct ApiEndpoint(String);
+
+impl Default for ApiEndpoint {
+	fn default() -> Self {
+		ApiEndpoint("api.fxtwitter.com".to_owned())
+	}
+}
+
+impl ApiEndpoint {
+	pub(crate) fn host(&self) -> &str {
+		&self.0
+	}
+}
+
+impl std::str::FromStr for ApiEndpoint {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		// Just a hostname. No scheme, path, or port.
+		let url = reqwest::Url::parse(&format!("https://{s}/"));
+		match url.as_ref().ok().and_then(|url| url.host_str()) {
+			Some(host) if host.eq_ignore_ascii_case(s) && host.contains('.') => Ok(ApiEndpoint(host.to_owned())),
+			_ => anyhow::bail!("expected a hostname like api.fxtwitter.com"),
+		}
+	}
+}
+
+impl std::fmt::Display for ApiEndpoint {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+/// Images narrower/shorter than this many pixels aren't worth uploading (tracking pixels, tiny icons).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct MinMediaSize(u32);
+
+impl Default for MinMediaSize {
+	fn default() -> Self {
+		MinMediaSize(100)
+	}
+}
+
+impl MinMediaSize {
+	pub(crate) fn get(self) -> u32 {
+		self.0
+	}
+}
+
+impl std::str::FromStr for MinMediaSize {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		match s.parse() {
+			Ok(px) => Ok(MinMediaSize(px)),
+			Err(_) => anyhow::bail!("expected a number of pixels (0 to allow everything)"),
+		}
+	}
+}
+
+impl std::fmt::Display for MinMediaSize {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}px", self.0)
+	}
+}
+
+/// Images bigger than this get scaled down before they're uploaded. Needs the `image-resize` feature.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct MaxMediaSize(Option<u32>);
+
+impl MaxMediaSize {
+	pub(crate) fn get(self) -> Option<u32> {
+		self.0
+	}
+}
+
+impl std::str::FromStr for MaxMediaSize {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		anyhow::ensure!(
+			cfg!(feature = "image-resize"),
+			"this build can't resize images (it needs the image-resize feature)"
+		);
+		match s {
+			"unlimited" => Ok(MaxMediaSize(None)),
+			_ => match s.parse() {
+				Ok(px) if px > 0 => Ok(MaxMediaSize(Some(px))),
+				_ => anyhow::bail!("expected a number of pixels or unlimited"),
+			},
+		}
+	}
+}
+
+impl std::fmt::Display for MaxMediaSize {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self.0 {
+			Some(px) => write!(f, "{px}px"),
+			None => f.write_str("unlimited"),
+		}
+	}
+}
+
+// Stored as JSON with #[serde(default)] so adding fields doesn't break older rows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct RoomSettings {
+	pub sensitive: SensitiveMode,
+	pub msgtype: Msgtype,
+	/// Whether to send the text part of posts. Off means just the media (if there is any).
+	pub echo: Toggle,
+	pub format: Format,
+	pub language: Language,
+	pub timezone: Timezone,
+	pub api_endpoint: ApiEndpoint,
+	pub reply_style: ReplyStyle,
+	/// Whether to post a "fetching" message right away and edit the post into it once it's ready.
+	pub progress: Toggle,
+	pub min_media_width: MinMediaSize,
+	pub min_media_height: MinMediaSize,
+	pub max_media_width: MaxMediaSize,
+	pub max_media_height: MaxMediaSize,
+	/// Whether to show the client a tweet was posted from (like "Twitter for iPhone") next to its timestamp.
+	pub show_source: Toggle,
+	/// Whether twitter "GIFs" (really short silent mp4s) get posted as the original video instead of a converted gif.
+	pub gif_as_video: Toggle,
+	/// Whether video thumbnails get fetched & uploaded. Turning it off saves a download and an upload per video.
+	pub thumbnails: Toggle,
+	/// Whether to send a map pin (`m.location`) for posts that have coordinates.
+	pub location: Toggle,
+	/// Whether tweets show the author line and the stats/timestamp footer or just the text.
+	pub embed_author: Toggle,
+}
+
+impl Default for RoomSettings {
+	fn default() -> Self {
+		RoomSettings {
+			sensitive: Default::default(),
+			msgtype: Default::default(),
+			echo: Toggle::On,
+			format: Default::default(),
+			language: Default::default(),
+			timezone: Default::default(),
+			api_endpoint: Default::default(),
+			reply_style: Default::default(),
+			progress: Toggle::Off,
+			min_media_width: Default::default(),
+			min_media_height: Default::default(),
+			max_media_width: Default::default(),
+			max_media_height: Default::default(),
+			show_source: Toggle::Off,
+			gif_as_video: Toggle::Off,
+			thumbnails: Toggle::On,
+			location: Toggle::Off,
+			embed_author: Toggle::On,
+		}
+	}
+}
+
+impl RoomSettings {
+	pub(crate) fn load(room_id: &RoomId) -> anyhow::Result<RoomSettings> {
+		let conn = db::open()?;
+		let settings: Option<String> = conn
+			.query_row(
+				"SELECT settings FROM RoomSettings WHERE room_id = ?1;",
+				(room_id.as_str(),),
+				|r| r.get(0),
+			)
+			.optional()?;
+		Ok(match settings {
+			Some(settings) => serde_json::from_str(&settings)?,
+			None => RoomSettings::default(),
+		})
+	}
+
+	/// `load()` but falls back to the defaults so a broken database doesn't stop posts from going out.
+	pub(crate) fn get(room_id: &RoomId) -> RoomSettings {
+		RoomSettings::load(room_id).unwrap_or_else(|e| {
+			println!("  failed to load settings for {room_id}: {e:?}");
+			RoomSettings::default()
+		})
+	}
+
+	pub(crate) fn persist(&self, room_id: &RoomId) -> anyhow::Result<()> {
+		let settings = serde_json::to_string(self)?;
+		let conn = db::open()?;
+		conn.execute(
+			"
+			INSERT INTO RoomSettings (room_id, settings)
+			VALUES (?1, ?2)
+			ON CONFLICT (room_id)
+			DO UPDATE SET settings = ?2;
+			",
+			(room_id.as_str(), &settings),
+		)?;
+		conn.close().map_err(|(_, e)| e)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn old_row_gets_defaults() {
+		// A row from before most of the settings existed.
+		let settings: RoomSettings =
+			serde_json::from_str(r#"{"sensitive":"spoiler","msgtype":"text","echo":"off"}"#).unwrap();
+		assert_eq!(settings.sensitive, SensitiveMode::Spoiler);
+		assert_eq!(settings.msgtype, Msgtype::Text);
+		assert_eq!(settings.echo, Toggle::Off);
+
+		assert_eq!(settings.format, Format::Full);
+		assert_eq!(settings.language, Language::En);
+		assert_eq!(settings.timezone, Timezone::default());
+		assert_eq!(settings.api_endpoint.host(), "api.fxtwitter.com");
+		assert_eq!(settings.reply_style, ReplyStyle::None);
+		assert_eq!(settings.progress, Toggle::Off);
+		assert_eq!(settings.min_media_width.get(), 100);
+		assert_eq!(settings.max_media_height.get(), None);
+		assert_eq!(settings.show_source, Toggle::Off);
+		assert_eq!(settings.gif_as_video, Toggle::Off);
+		assert_eq!(settings.thumbnails, Toggle::On);
+		assert_eq!(settings.location, Toggle::Off);
+		assert_eq!(settings.embed_author, Toggle::On);
+	}
+
+	#[test]
+	fn empty_row_is_default() {
+		let settings: RoomSettings = serde_json::from_str("{}").unwrap();
+		assert_eq!(
+			serde_json::to_value(&settings).unwrap(),
+			serde_json::to_value(RoomSettings::default()).unwrap()
+		);
+	}
+}
@@ -0,0 +1,39 @@
+// Post text goes through matrix-sdk's send queue, which persists unsent events in the store and retries them.
+// The queue disables itself after a few failures though, so this turns it back on.
+
+use std::time::Duration;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::task::AbortOnDropHandle;
+
+const REENABLE_DELAY: Duration = Duration::from_secs(30);
+
+/// Resends anything left over from the last session and keeps the queue enabled until the handle is dropped.
+pub(crate) async fn start(client: &matrix_sdk::Client) -> AbortOnDropHandle<()> {
+	let send_queue = client.send_queue();
+	let mut errors = send_queue.subscribe_errors();
+	send_queue.respawn_tasks_for_rooms_with_unsent_requests().await;
+
+	let client = client.clone();
+	AbortOnDropHandle::new(tokio::spawn(async move {
+		loop {
+			let error = match errors.recv().await {
+				Ok(error) => error,
+				Err(RecvError::Lagged(_)) => continue,
+				Err(RecvError::Closed) => break,
+			};
+			println!("send queue error in {}: {:?}", error.room_id, error.error);
+			// Unrecoverable ones (like a too-large event) are parked by matrix-sdk and won't go anywhere.
+			if !error.is_recoverable {
+				continue;
+			}
+			let client = client.clone();
+			tokio::spawn(async move {
+				tokio::time::sleep(REENABLE_DELAY).await;
+				if let Some(room) = client.get_room(&error.room_id) {
+					room.send_queue().set_enabled(true);
+				}
+			});
+		}
+	}))
+}
@@ -0,0 +1,121 @@
+use matrix_sdk::ruma::RoomId;
+use rusqlite::OptionalExtension;
+
+use crate::db;
+use crate::i18n;
+use crate::i18n::Language;
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct TweetStats {
+	pub tweets_processed: i64,
+	pub media_uploaded: i64,
+	pub errors: i64,
+	pub last_activity: i64,
+}
+
+impl TweetStats {
+	pub(crate) fn localized(&self, lang: Language) -> String {
+		let last_activity = jiff::Timestamp::from_second(self.last_activity).unwrap_or_default();
+		format!(
+			"{}: {} | {}: {} | {}: {} | {}: {}",
+			i18n::tr(lang, "stats_posts"),
+			self.tweets_processed,
+			i18n::tr(lang, "stats_media"),
+			self.media_uploaded,
+			i18n::tr(lang, "stats_errors"),
+			self.errors,
+			i18n::tr(lang, "stats_last_activity"),
+			last_activity.strftime("%F %T")
+		)
+	}
+}
+
+impl std::fmt::Display for TweetStats {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.localized(Language::En))
+	}
+}
+
+fn try_record(room_id: &RoomId, tweets_processed: i64, media_uploaded: i64, errors: i64) -> anyhow::Result<()> {
+	let conn = db::open()?;
+	conn.execute(
+		"
+		INSERT INTO TweetStats (room_id, tweets_processed, media_uploaded, errors, last_activity)
+		VALUES (?1, ?2, ?3, ?4, ?5)
+		ON CONFLICT (room_id)
+		DO UPDATE SET
+			tweets_processed = tweets_processed + ?2,
+			media_uploaded = media_uploaded + ?3,
+			errors = errors + ?4,
+			last_activity = ?5;
+		",
+		(
+			room_id.as_str(),
+			tweets_processed,
+			media_uploaded,
+			errors,
+			jiff::Timestamp::now().as_second(),
+		),
+	)?;
+	Ok(())
+}
+
+/// Bumps the counters for a room. Failing to count things isn't worth failing a post over.
+pub(crate) fn record(room_id: &RoomId, tweets_processed: i64, media_uploaded: i64, errors: i64) {
+	if let Err(e) = try_record(room_id, tweets_processed, media_uploaded, errors) {
+		println!("  failed to record stats for {room_id}: {e:?}");
+	}
+}
+
+pub(crate) fn get(room_id: &RoomId) -> anyhow::Result<TweetStats> {
+	let conn = db::open()?;
+	let stats = conn
+		.query_row(
+			"SELECT tweets_processed, media_uploaded, errors, last_activity FROM TweetStats WHERE room_id = ?1;",
+			(room_id.as_str(),),
+			|r| {
+				Ok(TweetStats {
+					tweets_processed: r.get(0)?,
+					media_uploaded: r.get(1)?,
+					errors: r.get(2)?,
+					last_activity: r.get(3)?,
+				})
+			},
+		)
+		.optional()?;
+	Ok(stats.unwrap_or_default())
+}
+
+/// Everything added up, plus how many rooms have stats.
+pub(crate) fn global() -> anyhow::Result<(TweetStats, i64)> {
+	let conn = db::open()?;
+	Ok(conn.query_row(
+		"
+		SELECT
+			COALESCE(SUM(tweets_processed), 0),
+			COALESCE(SUM(media_uploaded), 0),
+			COALESCE(SUM(errors), 0),
+			COALESCE(MAX(last_activity), 0),
+			COUNT(*)
+		FROM TweetStats;
+		",
+		(),
+		|r| {
+			Ok((
+				TweetStats {
+					tweets_processed: r.get(0)?,
+					media_uploaded: r.get(1)?,
+					errors: r.get(2)?,
+					last_activity: r.get(3)?,
+				},
+				r.get(4)?,
+			))
+		},
+	)?)
+}
+
+pub(crate) fn reset(room_id: &RoomId) -> anyhow::Result<()> {
+	let conn = db::open()?;
+	conn.execute("DELETE FROM TweetStats WHERE room_id = ?1;", (room_id.as_str(),))?;
+	Ok(())
+}
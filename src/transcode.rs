@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+// Leave some room for the container & audio so the output actually fits.
+const AUDIO_BITRATE: u64 = 64_000;
+const MAX_ATTEMPTS: u32 = 3;
+
+async fn duration_secs(path: &Path) -> anyhow::Result<f64> {
+	let output = tokio::process::Command::new("ffprobe")
+		.args(["-v", "error", "-show_entries", "format=duration", "-of", "csv=p=0"])
+		.arg(path)
+		.output()
+		.await
+		.context("Failed to run ffprobe")?;
+	anyhow::ensure!(
+		output.status.success(),
+		"ffprobe failed: {}",
+		String::from_utf8_lossy(&output.stderr)
+	);
+	let duration: f64 = String::from_utf8_lossy(&output.stdout)
+		.trim()
+		.parse()
+		.context("Failed to parse ffprobe duration")?;
+	anyhow::ensure!(duration > 0.0, "ffprobe says the video is {duration}s long");
+	Ok(duration)
+}
+
+/// Re-encodes a video as an h264 mp4 with a bitrate low enough to fit in `target_size_bytes`.
+/// Tries a couple of times with lower bitrates if ffmpeg overshoots.
+pub(crate) async fn transcode_video(input: &Path, target_size_bytes: u64) -> anyhow::Result<Vec<u8>> {
+	let output_file = tempfile::Builder::new().suffix(".mp4").tempfile()?;
+
+	let duration = duration_secs(input).await?;
+	let mut video_bitrate = (((target_size_bytes * 8) as f64 / duration * 0.9) as u64).saturating_sub(AUDIO_BITRATE);
+
+	for attempt in 1..=MAX_ATTEMPTS {
+		anyhow::ensure!(video_bitrate > 0, "video is too long to fit in {target_size_bytes} bytes");
+		println!("  transcoding to {}kbps (attempt {attempt})", video_bitrate / 1000);
+
+		let output = tokio::process::Command::new("ffmpeg")
+			.args(["-y", "-v", "error", "-i"])
+			.arg(input)
+			.args(["-c:v", "libx264", "-preset", "veryfast"])
+			.args(["-b:v", &video_bitrate.to_string()])
+			.args(["-maxrate", &video_bitrate.to_string()])
+			.args(["-bufsize", &(video_bitrate * 2).to_string()])
+			.args(["-c:a", "aac", "-b:a", &AUDIO_BITRATE.to_string()])
+			.args(["-movflags", "+faststart"])
+			.arg(output_file.path())
+			.output()
+			.await
+			.context("Failed to run ffmpeg")?;
+		anyhow::ensure!(
+			output.status.success(),
+			"ffmpeg failed: {}",
+			String::from_utf8_lossy(&output.stderr)
+		);
+
+		let data = tokio::fs::read(output_file.path()).await?;
+		if data.len() as u64 <= target_size_bytes {
+			return Ok(data);
+		}
+		video_bitrate = video_bitrate * 3 / 4;
+	}
+
+	anyhow::bail!("couldn't get the video under {target_size_bytes} bytes after {MAX_ATTEMPTS} attempts")
+}
@@ -4,7 +4,11 @@ use reqwest::Url;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::ARGS;
 use crate::HTTP;
+use crate::room_settings::ApiEndpoint;
+use crate::room_settings::RoomSettings;
+use crate::room_settings::Toggle;
 
 pub(super) const TARGETS: &[&str] = &[
 	"cunnyx.com",
@@ -22,10 +26,50 @@ pub(super) const TARGETS: &[&str] = &[
 	"xcancel.com",
 	"xfixup.com",
 ];
+const _: () = assert!(crate::url_util::all_valid_hostnames(TARGETS), "typo in TARGETS");
+
+/// Parses `--nitter-api nitter.example.com=api.example.com`.
+pub(super) fn parse_nitter_api(s: &str) -> Result<(String, ApiEndpoint), String> {
+	let (nitter, api) = s.split_once('=').ok_or("expected NITTER_HOST=API_HOST")?;
+	let api = api.parse().map_err(|e| format!("{api}: {e}"))?;
+	Ok((nitter.to_ascii_lowercase(), api))
+}
+
+/// The API host an operator set up for a nitter instance with `--nitter-api`.
+pub(super) fn nitter_api(host: &str) -> Option<&'static ApiEndpoint> {
+	ARGS.nitter_api
+		.iter()
+		.find(|(nitter, _)| nitter.eq_ignore_ascii_case(host))
+		.map(|(_, api)| api)
+}
+
+/// Whether a tweet link makes it through `--filter-regex` (everything does if there isn't one).
+pub(super) fn passes_filter(url: &Url) -> bool {
+	ARGS.filter_regex.is_empty() || ARGS.filter_regex.iter().any(|re| re.is_match(url.as_str()))
+}
+
+/// IDs are strings now but some API versions (and self-hosted instances) send numbers.
+fn string_or_int_to_string<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum StringOrInt {
+		String(String),
+		Int(u64),
+	}
+	Ok(match StringOrInt::deserialize(deserializer)? {
+		StringOrInt::String(s) => s,
+		StringOrInt::Int(n) => n.to_string(),
+	})
+}
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Author {
 	pub avatar_url: Url,
+	#[serde(rename = "followers")]
+	pub followers_count: Option<i64>,
+	#[serde(rename = "following")]
+	pub following_count: Option<i64>,
+	#[serde(deserialize_with = "string_or_int_to_string")]
 	pub id: String,
 	pub name: String,
 	pub screen_name: String,
@@ -37,11 +81,15 @@ pub(crate) struct VideoFormats {
 	pub container: String,
 	pub url: Url,
 }
+#[serde_with::serde_as]
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Videos {
 	pub format: String,
 	pub formats: Vec<VideoFormats>,
-	pub duration: f64,
+	/// fxtwitter sends fractional seconds like `15.7`.
+	#[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
+	pub duration: std::time::Duration,
+	#[serde(deserialize_with = "string_or_int_to_string")]
 	pub id: String,
 	pub thumbnail_url: Url,
 	pub r#type: String,
@@ -51,6 +99,7 @@ pub(crate) struct Videos {
 }
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Photos {
+	#[serde(deserialize_with = "string_or_int_to_string")]
 	pub id: String,
 	pub r#type: String,
 	pub url: Url,
@@ -67,12 +116,48 @@ pub(crate) struct Mosaic {
 	pub formats: MosaicFormats,
 	pub r#type: String,
 }
+/// Embedded links like youtube videos.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ExternalMedia {
+	pub r#type: String,
+	pub url: Url,
+	pub thumbnail_url: Option<Url>,
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+}
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Media {
+	pub external: Option<ExternalMedia>,
 	pub mosaic: Option<Mosaic>,
 	pub photos: Option<Vec<Photos>>,
 	pub videos: Option<Vec<Videos>>,
 }
+impl Media {
+	/// Every photo in the tweet (there can be up to 4).
+	pub fn all_photos(&self) -> Vec<&Photos> {
+		self.photos.iter().flatten().collect()
+	}
+}
+/// Where a tweet was posted from, if the author shared it.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TweetGeo {
+	pub latitude: f64,
+	pub longitude: f64,
+}
+/// Link preview cards (like for news articles) on tweets that don't have photos or videos.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TweetCard {
+	pub url: Url,
+	pub title: String,
+	pub description: Option<String>,
+	pub thumbnail_url: Option<Url>,
+}
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TweetUrl {
+	pub display_url: String,
+	pub expanded_url: Url,
+	pub url: String,
+}
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Tweet {
 	#[serde(flatten)]
@@ -82,16 +167,27 @@ pub(crate) struct Tweet {
 #[derive(Serialize, Deserialize)]
 pub(crate) struct TweetInner {
 	pub author: Author,
+	pub card: Option<TweetCard>,
 	pub created_at: String,
 	#[serde(with = "jiff::fmt::serde::timestamp::second::required")]
 	pub created_timestamp: jiff::Timestamp,
+	pub geo: Option<TweetGeo>,
+	pub hashtags: Option<Vec<String>>,
+	#[serde(deserialize_with = "string_or_int_to_string")]
 	pub id: String,
+	#[serde(default)]
+	pub is_quote_status: bool,
 	pub likes: i64,
 	pub media: Option<Media>,
+	#[serde(default)]
+	pub possibly_sensitive: bool,
 	pub replies: i64,
 	pub retweets: i64,
+	/// The client it was posted from, like "Twitter for iPhone".
+	pub source: Option<String>,
 	pub text: String,
 	pub url: Url,
+	pub urls: Option<Vec<TweetUrl>>,
 	pub views: Option<i64>,
 }
 #[derive(Serialize, Deserialize)]
@@ -101,42 +197,234 @@ pub(crate) struct FxApiResponse {
 	pub tweet: Option<Tweet>,
 }
 
-pub(super) async fn get_post(mut url: Url) -> anyhow::Result<crate::Post> {
+/// `12345` -> `12.3K`
+fn format_count(n: i64) -> String {
+	let (n, suffix) = match n.unsigned_abs() {
+		0..1_000 => return n.to_string(),
+		// Anything that would round up to "1000.0K" goes in the M bucket instead.
+		1_000..999_950 => (n as f64 / 1_000.0, "K"),
+		_ => (n as f64 / 1_000_000.0, "M"),
+	};
+	let n = format!("{n:.1}");
+	format!("{}{suffix}", n.strip_suffix(".0").unwrap_or(&n))
+}
+
+/// ` 👥12.3K followers, 200 following` or nothing if fxtwitter didn't send the counts.
+fn format_author_counts(author: &Author) -> String {
+	let mut s = String::new();
+	if let Some(followers) = author.followers_count {
+		s.push_str(&format!(" 👥{} followers", format_count(followers)));
+	}
+	if let Some(following) = author.following_count {
+		s.push_str(if s.is_empty() { " 👥" } else { ", " });
+		s.push_str(&format!("{} following", format_count(following)));
+	}
+	s
+}
+
+/// Escapes the tweet text and turns its hashtags & `t.co` links into real links.
+fn text_html(tweet: &TweetInner) -> String {
+	let is_word = |c: char| c.is_alphanumeric() || c == '_';
+	let tags: Vec<&str> = tweet.hashtags.iter().flatten().map(String::as_str).collect();
+	let escaped = htmlize::escape_text(&tweet.text);
+	let mut html = String::with_capacity(escaped.len());
+	let mut rest = &*escaped;
+	// Whether the text before `rest` ends in a word character, so `foo#tag` isn't treated as a tag.
+	let mut prev_word = false;
+	while let Some((before, after)) = rest.split_once('#') {
+		html.push_str(before);
+		let after_word = before.chars().next_back().map_or(prev_word, is_word);
+		// Only whole tags so `#art` doesn't eat the front of `#artwork`.
+		let (tag, after) = after.split_at(after.find(|c: char| !is_word(c)).unwrap_or(after.len()));
+		if !after_word && tags.contains(&tag) {
+			let mut href = Url::parse("https://x.com/hashtag/").unwrap();
+			href.path_segments_mut().unwrap().pop_if_empty().push(tag);
+			html.push_str(&format!(
+				r#"<a href="{}">#{tag}</a>"#,
+				htmlize::escape_attribute(href.as_str())
+			));
+		} else {
+			html.push('#');
+			html.push_str(tag);
+		}
+		prev_word = !tag.is_empty();
+		rest = after;
+	}
+	html.push_str(rest);
+
+	for url in tweet.urls.iter().flatten() {
+		let link = format!(
+			r#"<a href="{}">{}</a>"#,
+			htmlize::escape_attribute(url.expanded_url.as_str()),
+			htmlize::escape_text(&url.display_url)
+		);
+		html = html.replace(&*htmlize::escape_text(&url.url), &link);
+	}
+
+	html.lines().join("<br>")
+}
+
+/// Grabs the `123` from `/user/status/123/photo/1`.
+/// Also works for links that had text glued onto them like `/user/status/123…` (`/user/status/123%E2%80%A6`).
+pub(super) fn tweet_id(url: &Url) -> Option<&str> {
+	url.path_segments()?
+		.skip_while(|segment| *segment != "status")
+		.nth(1)?
+		.split(|c: char| !c.is_ascii_digit())
+		.next()
+		.filter(|id| !id.is_empty())
+}
+
+/// `https://x.com/user/status/123?s=20` -> `https://api.fxtwitter.com/user/status/123` (with `api.fxtwitter.com` being `api_host`)
+/// Links without a username (`/status/123`, `/i/web/status/123`) get `i` like twitter does.
+/// Ports, fragments & such from nitter links are dropped.
+pub(super) fn api_url(url: Url, api_host: &str) -> anyhow::Result<Url> {
+	let id = tweet_id(&url).context("Failed to grab the tweet ID")?;
+	let user = url
+		.path_segments()
+		.and_then(|mut s| s.next())
+		.filter(|user| *user != "status" && !user.is_empty())
+		.filter(|user| user.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+		.unwrap_or("i");
+	Url::parse(&format!("https://{api_host}/{user}/status/{id}")).context("Bad API host")
+}
+
+/// The highest bitrate mp4 that should fit in `max_size` going by `duration * bitrate`.
+/// Playlists (m3u8) don't have a bitrate so they're skipped.
+fn select_best_format(formats: &[VideoFormats], duration: std::time::Duration, max_size: u64) -> Option<&VideoFormats> {
+	formats
+		.iter()
+		.filter(|f| f.container == "mp4")
+		.filter_map(|f| Some((f, f.bitrate?)))
+		.filter(|(_, bitrate)| (duration.as_secs_f64() * f64::from(*bitrate) / 8.0) as u64 <= max_size)
+		.max_by_key(|(_, bitrate)| *bitrate)
+		.map(|(f, _)| f)
+}
+
+/// `max_upload_size` is used to pick which video quality to grab.
+pub(super) async fn get_post(url: Url, max_upload_size: u64, settings: &RoomSettings) -> anyhow::Result<crate::Post> {
 	let mut post = crate::Post::default();
 
-	url.set_host(Some("api.fxtwitter.com")).unwrap();
-	url.set_path(&url.path().split('/').skip(1).take(3).join("/"));
-	url.set_query(None);
+	let api_endpoint = url.host_str().and_then(nitter_api).unwrap_or(&settings.api_endpoint);
+	let url = api_url(url, api_endpoint.host())?;
 	println!("{url}");
 	let response = HTTP
 		.get(url)
 		.send()
 		.await
 		.context("Failed to fetch api.fxtwitter.com results")?;
+	// fxtwitter also sends JSON for things like 404s so only check the status for everything else.
+	let is_json = response
+		.headers()
+		.get(reqwest::header::CONTENT_TYPE)
+		.and_then(|v| v.to_str().ok())
+		.is_some_and(|v| v.starts_with("application/json"));
+	let response = if is_json {
+		response
+	} else {
+		response.error_for_status().context("Bad status")?
+	};
 	let response = response
 		.json::<FxApiResponse>()
 		.await
 		.context("failed to parse as JSON into FxApiResponse")?;
-	let Tweet { tweet, quote } = response.tweet.context("response.tweet was None")?;
+	let explanation = match response.code {
+		200 => None,
+		401 => Some("tweet_private".to_owned()),
+		404 => Some("tweet_not_found".to_owned()),
+		500.. => Some("api_error".to_owned()),
+		_ => Some(response.message.clone()),
+	};
+	if let Some(explanation) = explanation {
+		println!("  api.fxtwitter.com returned code {}: {}", response.code, response.message);
+		return Err(crate::PublicError(explanation).into());
+	}
+	let Tweet { tweet, quote } = response.tweet.with_context(|| {
+		format!(
+			"api.fxtwitter.com returned code {} ({}) but no tweet",
+			response.code, response.message
+		)
+	})?;
 
 	let quote_plain = if let Some(quote) = &quote {
 		let t = quote.text.lines().join("\n> ");
-		format!("\n> {} (@{})\n{}", quote.author.name, quote.author.screen_name, t)
+		format!(
+			"\n🔁 Quote tweet\n> {} (@{})\n{}",
+			quote.author.name, quote.author.screen_name, t
+		)
+	} else if tweet.is_quote_status {
+		// The quoted tweet was deleted or is private.
+		"\n🔁 Quote tweet".into()
 	} else {
 		"".into()
 	};
 
+	let author_counts = format_author_counts(&tweet.author);
+	let created_at = tweet.created_timestamp.to_zoned(settings.timezone.get());
+	let source = match (&tweet.source, settings.show_source) {
+		(Some(source), Toggle::On) if !source.is_empty() => format!(" · {source}"),
+		_ => String::new(),
+	};
+
+	let external = tweet.media.as_ref().and_then(|m| m.external.as_ref());
+	let external_plain = external.map(|e| format!("\n🔗 {}", e.url)).unwrap_or_default();
+	let external_html = external
+		.map(|e| {
+			let safe_url = htmlize::escape_attribute(e.url.as_str());
+			format!(
+				r#"<p class="fx-embed-external">🔗 <a href="{safe_url}">{}</a></p>"#,
+				htmlize::escape_text(e.url.as_str())
+			)
+		})
+		.unwrap_or_default();
+
+	let card_plain = tweet
+		.card
+		.as_ref()
+		.map(|c| {
+			let description = c.description.as_ref().map(|d| format!("\n{d}")).unwrap_or_default();
+			format!("\n📰 {}{description}\n{}", c.title, c.url)
+		})
+		.unwrap_or_default();
+	let card_html = tweet
+		.card
+		.as_ref()
+		.map(|c| {
+			let description = c
+				.description
+				.as_ref()
+				.map(|d| format!("<br>{}", htmlize::escape_text(d)))
+				.unwrap_or_default();
+			format!(
+				r#"<p class="fx-embed-card">📰 <a href="{}">{}</a>{description}</p>"#,
+				htmlize::escape_attribute(c.url.as_str()),
+				htmlize::escape_text(&c.title)
+			)
+		})
+		.unwrap_or_default();
+
+	let show_author = settings.embed_author == Toggle::On;
+	let views = tweet.views.map(|n| n.to_string()).unwrap_or_else(|| "?".to_string());
+
+	let author_plain = if show_author {
+		format!("{} (@{})\n", tweet.author.name, tweet.author.screen_name)
+	} else {
+		String::new()
+	};
+	let bottom_plain = if show_author {
+		format!(
+			"\n💬{} ♻️{} ❤️{} 👁️{views}{author_counts}\n{}{source}",
+			tweet.replies,
+			tweet.retweets,
+			tweet.likes,
+			created_at.strftime("%F %T")
+		)
+	} else {
+		String::new()
+	};
 	post.body_plain = format!(
-		"{} (@{})\n{}{}\n💬{} ♻️{} ❤️{} 👁️{}\n{}",
-		tweet.author.name,
-		tweet.author.screen_name,
-		tweet.text,
-		quote_plain,
-		tweet.replies,
-		tweet.retweets,
-		tweet.likes,
-		tweet.views.map(|n| n.to_string()).unwrap_or_else(|| "?".to_string()),
-		tweet.created_timestamp.strftime("%F %T")
+		"{author_plain}{}{external_plain}{card_plain}{quote_plain}{bottom_plain}",
+		tweet.text
 	);
 
 	let quote_html = if let Some(quote) = &quote {
@@ -144,7 +432,7 @@ pub(super) async fn get_post(mut url: Url) -> anyhow::Result<crate::Post> {
 		tweet_url.set_host(Some("x.com")).unwrap();
 		let safe_author_name = htmlize::escape_text(&quote.author.name);
 		let safe_author_handle = quote.author.screen_name.as_str();
-		let safe_tweet_body = htmlize::escape_text(&quote.text).lines().join("<br>");
+		let safe_tweet_body = text_html(quote);
 		format!(
 			r##"<blockquote class="fx-embed-quote" background-color="#6364FF">
 			<p class="fx-embed-quote-author">
@@ -161,6 +449,8 @@ pub(super) async fn get_post(mut url: Url) -> anyhow::Result<crate::Post> {
 			</p>
 			</blockquote>"##
 		)
+	} else if tweet.is_quote_status {
+		r#"<p class="fx-embed-quote-unavailable">🔁 Quote tweet</p>"#.into()
 	} else {
 		"".into()
 	};
@@ -168,71 +458,222 @@ pub(super) async fn get_post(mut url: Url) -> anyhow::Result<crate::Post> {
 	let mut tweet_url = tweet.url.clone();
 	tweet_url.set_host(Some("x.com")).unwrap();
 	let safe_author_name = htmlize::escape_text(&tweet.author.name);
-	let safe_tweet_body = htmlize::escape_text(&tweet.text).lines().join("<br>");
+	let safe_tweet_body = text_html(&tweet);
 	// TODO: alt text
-	post.body_html = format!(
-		r##"<blockquote class="fx-embed" background-color="#6364FF">
-		<p class="fx-embed-author">
+	let author_html = if show_author {
+		format!(
+			r##"<p class="fx-embed-author">
 			<!-- <img data-mx-emoticon height="24" src="{{author_icon_url}}" title="Author icon" alt="">
 			&nbsp; -->
 			<span>
 				<a href="{tweet_url}">{safe_author_name} (@{})</a>
 			</span>
-		</p>
-		<p class="fx-embed-text">
+		</p>"##,
+			tweet.author.screen_name
+		)
+	} else {
+		String::new()
+	};
+	let bottom_html = if show_author {
+		format!(
+			r##"<p class="fx-bottom">
 			<span>
-				{safe_tweet_body}
+				💬{} ♻️{} ❤️{} 👁️{views}{author_counts}
 			</span>
-		</p>
-		<!-- {{file_html}} -->
-		{quote_html}
-		<p class="fx-bottom">
+			<br>
 			<span>
-				💬{} ♻️{} ❤️{} 👁️{}
+				{}{}
 			</span>
-			<br>
+		</p>"##,
+			tweet.replies,
+			tweet.retweets,
+			tweet.likes,
+			created_at.strftime("%F %T"),
+			htmlize::escape_text(&source)
+		)
+	} else {
+		String::new()
+	};
+	post.body_html = format!(
+		r##"<blockquote class="fx-embed" background-color="#6364FF">
+		{author_html}
+		<p class="fx-embed-text">
 			<span>
-				{}
+				{safe_tweet_body}
 			</span>
 		</p>
-		</blockquote>"##,
-		tweet.author.screen_name,
-		tweet.replies,
-		tweet.retweets,
-		tweet.likes,
-		tweet.views.map(|n| n.to_string()).unwrap_or_else(|| "?".to_string()),
-		tweet.created_timestamp.strftime("%F %T")
+		<!-- {{file_html}} -->
+		{external_html}
+		{card_html}
+		{quote_html}
+		{bottom_html}
+		</blockquote>"##
 	);
 
+	post.sensitive = tweet.possibly_sensitive;
+	post.location = tweet.geo.as_ref().map(|geo| (geo.latitude, geo.longitude));
 	if let Some(media) = tweet.media {
+		let big_enough = |url: &Url, width: u32, height: u32| {
+			let big_enough = width >= settings.min_media_width.get() && height >= settings.min_media_height.get();
+			if !big_enough {
+				println!("  skipping {width}x{height} image {url}");
+			}
+			big_enough
+		};
 		// TODO: post ALL images and ALL videos...
 		if let Some(videos) = media.videos {
 			let video = &videos[0];
-			let mut url = videos[0].url.clone();
-			if video.r#type == "gif" {
+			let mut url = video.url.clone();
+			let as_gif = video.r#type == "gif" && settings.gif_as_video == Toggle::Off;
+			if as_gif {
 				url.set_path(&url.path().replace(".mp4", ".gif"));
 				url.set_host(Some("gif.fxtwitter.com")).unwrap();
+			} else if let Some(format) = select_best_format(&video.formats, video.duration, max_upload_size) {
+				url = format.url.clone();
 			}
-			post.media.push(crate::Media {
-				is_video: video.r#type != "gif",
-				url: url,
-				thumbnail_url: Some(video.thumbnail_url.clone()),
-			});
-		} else if let Some(mosaic) = media.mosaic {
-			post.media.push(crate::Media {
-				is_video: false,
-				url: mosaic.formats.webp.clone(),
-				thumbnail_url: None,
-			});
-		} else if let Some(photos) = media.photos {
-			let photo = &photos[0];
-			post.media.push(crate::Media {
-				is_video: false,
-				url: photo.url.clone(),
-				thumbnail_url: None,
-			})
+			post.media.push(
+				crate::Media::new(url, !as_gif)
+					.with_thumbnail(
+						big_enough(&video.thumbnail_url, video.width, video.height).then(|| video.thumbnail_url.clone()),
+					)
+					.with_duration(video.duration),
+			);
+		} else if let Some(mosaic) = &media.mosaic {
+			// The webp mosaic fails more often than the jpeg one. The first photo on its own is better than nothing,
+			// as long as it's not under the room's `!fx min-media-width`/`min-media-height`.
+			let first_photo = media
+				.all_photos()
+				.into_iter()
+				.find(|photo| big_enough(&photo.url, photo.width, photo.height))
+				.map(|photo| photo.url.clone());
+			post.media.push(
+				crate::Media::new(mosaic.formats.jpeg.clone(), false)
+					.with_fallbacks(std::iter::once(mosaic.formats.webp.clone()).chain(first_photo).collect()),
+			);
+		} else if let Some(photo) = media
+			.all_photos()
+			.into_iter()
+			.find(|photo| big_enough(&photo.url, photo.width, photo.height))
+		{
+			post.media.push(crate::Media::new(photo.url.clone(), false))
 		}
 	}
+	if post.media.is_empty()
+		&& let Some(thumbnail_url) = tweet.card.and_then(|c| c.thumbnail_url)
+	{
+		post.media.push(crate::Media::new(thumbnail_url, false));
+	}
 
 	Ok(post)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[derive(Deserialize)]
+	struct Id {
+		#[serde(deserialize_with = "string_or_int_to_string")]
+		id: String,
+	}
+
+	#[test]
+	fn id_string_or_int() {
+		assert_eq!(serde_json::from_str::<Id>(r#"{"id":"123"}"#).unwrap().id, "123");
+		assert_eq!(serde_json::from_str::<Id>(r#"{"id":123}"#).unwrap().id, "123");
+		assert!(serde_json::from_str::<Id>(r#"{"id":1.5}"#).is_err());
+		assert!(serde_json::from_str::<Id>(r#"{"id":true}"#).is_err());
+	}
+
+	#[test]
+	fn format_count_buckets() {
+		assert_eq!(format_count(999), "999");
+		assert_eq!(format_count(1_000), "1K");
+		assert_eq!(format_count(12_345), "12.3K");
+		assert_eq!(format_count(999_949), "999.9K");
+		assert_eq!(format_count(999_950), "1M");
+		assert_eq!(format_count(999_999), "1M");
+		assert_eq!(format_count(1_250_000), "1.2M");
+		assert_eq!(format_count(-12_345), "-12.3K");
+		assert_eq!(format_count(i64::MIN), "-9223372036854.8M");
+	}
+
+	#[test]
+	fn text_html_hashtags() {
+		let response: FxApiResponse =
+			serde_json::from_str(include_str!("testdata/fxtwitter_tweet_with_video.json")).unwrap();
+		let mut tweet = response.tweet.unwrap().tweet;
+		tweet.urls = None;
+		tweet.text = "#art & #artwork foo#art #art_ #art! #Art".to_owned();
+		tweet.hashtags = Some(vec!["art".to_owned(), "art".to_owned()]);
+		let link = r#"<a href="https://x.com/hashtag/art">#art</a>"#;
+		assert_eq!(text_html(&tweet), format!("{link} &amp; #artwork foo#art #art_ {link}! #Art"));
+	}
+
+	#[test]
+	fn fx_api_response_video() {
+		let response: FxApiResponse =
+			serde_json::from_str(include_str!("testdata/fxtwitter_tweet_with_video.json")).unwrap();
+		assert_eq!(response.code, 200);
+		let Tweet { tweet, quote } = response.tweet.unwrap();
+		assert!(quote.is_none());
+		assert_eq!(tweet.id, "1790000000000000001");
+		assert_eq!(tweet.author.id, "11348282");
+		assert_eq!(tweet.author.screen_name, "NASA");
+		assert_eq!(tweet.author.followers_count, Some(87654321));
+		assert_eq!(tweet.author.following_count, Some(180));
+		assert_eq!(tweet.created_timestamp.as_second(), 1715612400);
+		assert_eq!(tweet.hashtags.as_deref(), Some(&["Artemis".to_owned()][..]));
+		assert_eq!(tweet.urls.unwrap().first().unwrap().display_url, "nasa.gov/artemis");
+		assert_eq!((tweet.replies, tweet.retweets, tweet.likes), (1234, 5678, 91011));
+		assert_eq!(tweet.views, Some(12345678));
+		assert_eq!(tweet.source.as_deref(), Some("Sprinklr"));
+		// Missing from the response so they fall back to their defaults.
+		assert!(!tweet.is_quote_status);
+		assert!(!tweet.possibly_sensitive);
+
+		let media = tweet.media.unwrap();
+		assert!(media.photos.is_none() && media.mosaic.is_none());
+		let videos = media.videos.unwrap();
+		let video = videos.first().unwrap();
+		assert_eq!(video.id, "1790000000000000002");
+		assert_eq!((video.width, video.height), (1920, 1080));
+		assert_eq!(video.duration, std::time::Duration::from_millis(15_700));
+		assert_eq!(video.r#type, "video");
+		let [playlist, _, best] = video.formats.as_slice() else {
+			panic!("expected 3 formats");
+		};
+		assert_eq!(playlist.container, "m3u8");
+		assert_eq!(playlist.bitrate, None);
+		assert_eq!(best.bitrate, Some(10368000));
+	}
+
+	#[test]
+	fn fx_api_response_photos() {
+		let response: FxApiResponse =
+			serde_json::from_str(include_str!("testdata/fxtwitter_tweet_with_photos.json")).unwrap();
+		let Tweet { tweet, .. } = response.tweet.unwrap();
+		// Numeric IDs come out as strings too.
+		assert_eq!(tweet.id, "1780000000000000001");
+		assert_eq!(tweet.author.id, "123456789");
+		assert_eq!(tweet.author.followers_count, None);
+		assert_eq!(tweet.views, None);
+		assert!(tweet.possibly_sensitive);
+
+		let media = tweet.media.unwrap();
+		assert!(media.videos.is_none());
+		let photos = media.all_photos();
+		let [first, second] = photos.as_slice() else {
+			panic!("expected 2 photos");
+		};
+		assert_eq!(first.id, "1780000000000000002");
+		assert_eq!(second.id, "1780000000000000003");
+		assert_eq!((first.width, first.height), (4096, 2304));
+		assert_eq!(
+			second.url.as_str(),
+			"https://pbs.twimg.com/media/BBBBBBBBBBBBBBB.png?name=orig"
+		);
+		let mosaic = media.mosaic.unwrap();
+		assert_eq!(mosaic.formats.jpeg.host_str(), Some("mosaic.fxtwitter.com"));
+	}
+}
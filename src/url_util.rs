@@ -0,0 +1,127 @@
+use reqwest::Url;
+
+/// Twitter usernames are 1-15 characters of `[A-Za-z0-9_]`.
+fn is_valid_username(s: &str) -> bool {
+	(1..=15).contains(&s.len()) && s.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_')
+}
+
+/// Checks that the path looks like `/<username>/status/<id>[/...]` so things like `/settings/status` are ignored.
+/// `/i/status/<id>` isn't a tweet page either, even though `i` would pass as a username.
+pub(crate) fn is_valid_tweet_url(url: &Url) -> bool {
+	let Some(mut segments) = url.path_segments() else {
+		return false;
+	};
+	segments.next().is_some_and(|user| user != "i" && is_valid_username(user))
+		&& segments.next() == Some("status")
+		&& crate::twitter::tweet_id(url).is_some()
+}
+
+/// Lowercase dot-separated labels of `[a-z0-9-]` (not starting or ending with `-`) with at least one dot.
+/// Hostnames from `Url` are already lowercased so an uppercase entry would never match either.
+pub(crate) const fn is_valid_hostname(s: &str) -> bool {
+	let mut rest = s.as_bytes();
+	let mut dots = 0;
+	let mut label_len = 0;
+	let mut prev = 0;
+	while let Some((&c, tail)) = rest.split_first() {
+		if c == b'.' {
+			if label_len == 0 || prev == b'-' {
+				return false;
+			}
+			dots += 1;
+			label_len = 0;
+		} else if c.is_ascii_lowercase() || c.is_ascii_digit() || (c == b'-' && label_len > 0) {
+			label_len += 1;
+		} else {
+			return false;
+		}
+		prev = c;
+		rest = tail;
+	}
+	dots > 0 && label_len > 0 && prev != b'-'
+}
+
+/// For checking the `TARGETS` lists at compile time like `const _: () = assert!(all_valid_hostnames(TARGETS));`
+pub(crate) const fn all_valid_hostnames(hosts: &[&str]) -> bool {
+	let mut rest = hosts;
+	while let Some((host, tail)) = rest.split_first() {
+		if !is_valid_hostname(host) {
+			return false;
+		}
+		rest = tail;
+	}
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn valid(url: &str) -> bool {
+		is_valid_tweet_url(&Url::parse(url).unwrap())
+	}
+
+	#[test]
+	fn tweet_paths() {
+		assert!(valid("https://x.com/user/status/123"));
+		assert!(valid("https://x.com/user/status/123/photo/1"));
+		assert!(!valid("https://x.com/i/status/123"));
+		assert!(!valid("https://x.com/settings/status"));
+		assert!(!valid("https://x.com/user"));
+		assert!(!valid("https://x.com/user/likes/123"));
+	}
+
+	#[test]
+	fn username_lengths() {
+		assert!(!is_valid_username(""));
+		assert!(is_valid_username("a"));
+		assert!(is_valid_username("abcdefghijklmno"));
+		assert!(!is_valid_username("abcdefghijklmnop"));
+		assert!(!valid("https://x.com//status/123"));
+		assert!(valid("https://x.com/abcdefghijklmno/status/123"));
+		assert!(!valid("https://x.com/abcdefghijklmnop/status/123"));
+	}
+
+	#[test]
+	fn username_characters() {
+		assert!(is_valid_username("Some_User_99"));
+		for bad in ["some-user", "some.user", "some user", "üser", "user!", "@user"] {
+			assert!(!is_valid_username(bad), "{bad}");
+		}
+	}
+
+	#[test]
+	fn edge_cases() {
+		let cases = [
+			("https://x.com/user/status/123?s=20&t=abc", true),
+			("https://x.com/user/status/123#reply", true),
+			("https://mobile.twitter.com/user/status/123", true),
+			("https://x.com:8443/user/status/123", true),
+			("https://t.co/AbCdEf123", false),
+			("https://t.co/user/status", false),
+			("https://x.com/user/status/?s=20", false),
+		];
+		for (url, expected) in cases {
+			assert_eq!(valid(url), expected, "{url}");
+		}
+	}
+
+	#[test]
+	fn hostnames() {
+		assert!(is_valid_hostname("x.com"));
+		assert!(is_valid_hostname("nitter.poast.org"));
+		for bad in [
+			"twitter,com",
+			"localhost",
+			"x.com/",
+			".x.com",
+			"x..com",
+			"x.com.",
+			"-x.com",
+			"x-.com",
+			"X.com",
+		] {
+			assert!(!is_valid_hostname(bad), "{bad}");
+		}
+	}
+}
@@ -0,0 +1,162 @@
+// `POST /` with `room_id=!abc:example.org&tweet_url=https://x.com/...` (or the same as a JSON object)
+// to have a link posted without anyone sending it. Hand-rolled like the health check.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use matrix_sdk::RoomState;
+use matrix_sdk::ruma::OwnedRoomId;
+use reqwest::Url;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio_util::task::AbortOnDropHandle;
+
+use crate::Target;
+use crate::allowlist;
+use crate::whitelist;
+
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+/// So a client that opens a connection and trickles bytes (or nothing) can't hold the task forever.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Binds `addr` and serves until the handle is dropped (so it goes away with the session's client).
+pub(crate) async fn start(
+	addr: SocketAddr,
+	client: &matrix_sdk::Client,
+	secret: String,
+) -> anyhow::Result<AbortOnDropHandle<()>> {
+	let listener = TcpListener::bind(addr)
+		.await
+		.with_context(|| format!("failed to bind webhook address {addr}"))?;
+	println!("Webhook listening on {addr}");
+	let client = client.clone();
+	Ok(AbortOnDropHandle::new(tokio::spawn(async move {
+		loop {
+			match listener.accept().await {
+				Ok((stream, _)) => {
+					let client = client.clone();
+					let secret = secret.clone();
+					tokio::spawn(async move {
+						if let Err(e) = handle(stream, &client, &secret).await {
+							println!("webhook: {e:?}");
+						}
+					});
+				},
+				Err(e) => println!("webhook accept: {e:?}"),
+			}
+		}
+	})))
+}
+
+struct Request {
+	method: String,
+	headers: HashMap<String, String>,
+	body: String,
+}
+
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<Request> {
+	let mut buf = Vec::with_capacity(1024);
+	let header_end = loop {
+		if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+			break i;
+		}
+		anyhow::ensure!(buf.len() < MAX_REQUEST_SIZE, "headers too large");
+		anyhow::ensure!(stream.read_buf(&mut buf).await? > 0, "connection closed early");
+	};
+
+	let head = String::from_utf8_lossy(buf.get(..header_end).unwrap_or_default()).into_owned();
+	let mut lines = head.lines();
+	let method = lines.next().and_then(|l| l.split(' ').next()).unwrap_or_default().to_owned();
+	let headers: HashMap<String, String> = lines
+		.filter_map(|l| l.split_once(':'))
+		.map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_owned()))
+		.collect();
+
+	let content_length: usize = headers.get("content-length").and_then(|v| v.parse().ok()).unwrap_or(0);
+	anyhow::ensure!(content_length <= MAX_REQUEST_SIZE, "body too large");
+	let mut body = buf.split_off(header_end + 4);
+	body.reserve_exact(content_length.saturating_sub(body.len()));
+	while body.len() < content_length {
+		anyhow::ensure!(stream.read_buf(&mut body).await? > 0, "connection closed early");
+	}
+	body.truncate(content_length);
+
+	Ok(Request {
+		method,
+		headers,
+		body: String::from_utf8(body).context("body isn't UTF-8")?,
+	})
+}
+
+/// Doesn't bail early on the first mismatched byte.
+fn secret_matches(given: &str, secret: &str) -> bool {
+	given.len() == secret.len() && given.bytes().zip(secret.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Pulls `room_id` & `tweet_url` out of a form or JSON body.
+fn parse_body(request: &Request) -> Option<(String, String)> {
+	let is_json = request
+		.headers
+		.get("content-type")
+		.is_some_and(|v| v.starts_with("application/json"));
+	let fields: HashMap<String, String> = if is_json {
+		serde_json::from_str(&request.body).ok()?
+	} else {
+		let mut url = Url::parse("http://localhost/").unwrap();
+		url.set_query(Some(&request.body));
+		url.query_pairs().into_owned().collect()
+	};
+	Some((fields.get("room_id")?.clone(), fields.get("tweet_url")?.clone()))
+}
+
+async fn handle(mut stream: TcpStream, client: &matrix_sdk::Client, secret: &str) -> anyhow::Result<()> {
+	let request = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream))
+		.await
+		.context("timed out reading request")??;
+	let (status, message) = route(&request, client, secret);
+	let body = serde_json::json!({ "status": message }).to_string();
+	let response = format!(
+		"HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		body.len()
+	);
+	stream.write_all(response.as_bytes()).await?;
+	stream.shutdown().await?;
+	Ok(())
+}
+
+fn route(request: &Request, client: &matrix_sdk::Client, secret: &str) -> (&'static str, &'static str) {
+	if request.method != "POST" {
+		return ("405 Method Not Allowed", "POST only");
+	}
+	let authorization = request.headers.get("authorization").map(String::as_str).unwrap_or_default();
+	let given = authorization.strip_prefix("Bearer ").unwrap_or(authorization);
+	if !secret_matches(given, secret) {
+		return ("401 Unauthorized", "bad secret");
+	}
+	let Some((room_id, url)) = parse_body(request) else {
+		return ("400 Bad Request", "expected room_id and tweet_url");
+	};
+	let Some(room) = OwnedRoomId::try_from(room_id)
+		.ok()
+		.and_then(|room_id| client.get_room(&room_id))
+		.filter(|room| room.state() == RoomState::Joined)
+	else {
+		return ("404 Not Found", "not in that room");
+	};
+	if !whitelist::allowed(room.room_id()) {
+		return ("403 Forbidden", "room isn't whitelisted");
+	}
+	let room_domains = allowlist::get(room.room_id());
+	let Some(target) = Url::from_str(&url).ok().and_then(|url| Target::get(url, &room_domains)) else {
+		return ("422 Unprocessable Entity", "not a supported link");
+	};
+	tokio::spawn(async move {
+		crate::process_target(&room, target, None).await;
+	});
+	("202 Accepted", "queued")
+}
@@ -0,0 +1,52 @@
+// Rooms the bot responds in when running with `--whitelist-mode`.
+
+use matrix_sdk::ruma::RoomId;
+use rusqlite::OptionalExtension;
+
+use crate::db;
+
+pub(crate) fn list() -> anyhow::Result<Vec<String>> {
+	let conn = db::open()?;
+	let mut stmt = conn.prepare("SELECT room_id FROM WhitelistedRooms ORDER BY room_id;")?;
+	let rooms = stmt.query_map((), |r| r.get(0))?.collect::<Result<Vec<String>, _>>()?;
+	Ok(rooms)
+}
+
+pub(crate) fn contains(room_id: &RoomId) -> anyhow::Result<bool> {
+	let conn = db::open()?;
+	let found = conn
+		.query_row(
+			"SELECT 1 FROM WhitelistedRooms WHERE room_id = ?1;",
+			(room_id.as_str(),),
+			|_| Ok(()),
+		)
+		.optional()?;
+	Ok(found.is_some())
+}
+
+/// Always true unless `--whitelist-mode` is on. A broken database counts as not whitelisted.
+pub(crate) fn allowed(room_id: &RoomId) -> bool {
+	if !crate::ARGS.whitelist_mode {
+		return true;
+	}
+	contains(room_id).unwrap_or_else(|e| {
+		println!("  failed to check the room whitelist: {e:?}");
+		false
+	})
+}
+
+pub(crate) fn add(room_id: &RoomId) -> anyhow::Result<()> {
+	let conn = db::open()?;
+	conn.execute(
+		"INSERT OR IGNORE INTO WhitelistedRooms (room_id) VALUES (?1);",
+		(room_id.as_str(),),
+	)?;
+	Ok(())
+}
+
+/// Returns whether anything was removed.
+pub(crate) fn remove(room_id: &RoomId) -> anyhow::Result<bool> {
+	let conn = db::open()?;
+	let removed = conn.execute("DELETE FROM WhitelistedRooms WHERE room_id = ?1;", (room_id.as_str(),))?;
+	Ok(removed > 0)
+}